@@ -1,46 +1,83 @@
 use std::{
     cell::RefCell,
     collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
     rc::{Rc, Weak},
 };
-type NodeRef = Rc<RefCell<AcNode>>;
-type NodeWeakRef = Weak<RefCell<AcNode>>;
+
+use thiserror::Error;
+
+use crate::serial::{self, Reader, SerialError, put_u32, u32_at};
+type NodeRef<T> = Rc<RefCell<AcNode<T>>>;
+type NodeWeakRef<T> = Weak<RefCell<AcNode<T>>>;
 // 8+8+8+8=32byte
-///ac自动机节点
+///ac自动机节点，`T`是单词附带的值的类型
 #[derive(Debug)]
-pub struct AcNode {
+pub struct AcNode<T> {
     // 匹配失败的时候跳转位置,fail指针一定存在,这里有option是因为初始化的时候rust必须设置默认值
-    fail: Option<Weak<RefCell<AcNode>>>,
+    fail: Option<NodeWeakRef<T>>,
     // 上一个节点
-    last: Option<Weak<RefCell<AcNode>>>,
+    last: Option<NodeWeakRef<T>>,
+    // fail链上最近的单词节点，输出匹配时跳过fail链上不是单词的节点
+    output: Option<NodeWeakRef<T>>,
     // 子节点
-    child: HashMap<char, Rc<RefCell<AcNode>>>,
+    child: HashMap<char, NodeRef<T>>,
     is_word: bool,
+    // 单词的编号，is_word为true时有效
+    id: usize,
+    // 单词附带的值
+    value: Option<T>,
+    // 从根节点到当前节点的字节数，匹配时用来计算开始位置
+    depth: usize,
+    // 根节点上的标记，插入或者删除之后fail指针需要重新设置
+    dirty: bool,
+    // 根节点上的计数器，`push_str`插入的单词按顺序编号
+    next_id: usize,
 }
 
-impl AcNode {
+impl<T> AcNode<T> {
     ///创建一个ac自动机节点
-    pub fn new() -> NodeRef {
+    pub fn new() -> NodeRef<T> {
         let sself = Self {
             last: None,
             fail: None,
+            output: None,
             is_word: false,
             child: HashMap::new(),
+            id: 0,
+            value: None,
+            depth: 0,
+            dirty: false,
+            next_id: 0,
         };
         Rc::new(RefCell::new(sself))
     }
     // 获取当前节点的前一个节点
-    pub fn get_prev_node(node: &NodeRef) -> Option<NodeRef> {
+    pub fn get_prev_node(node: &NodeRef<T>) -> Option<NodeRef<T>> {
         let node = node.borrow().last.clone()?;
         node.upgrade()
     }
+    // fail链上最近的单词节点
+    fn get_output_node(node: &NodeRef<T>) -> Option<NodeRef<T>> {
+        let node = node.borrow().output.clone()?;
+        node.upgrade()
+    }
     ///获取传入节点的fail指向的节点
-    pub fn get_fail_node(node: &NodeRef) -> Option<NodeRef> {
+    pub fn get_fail_node(node: &NodeRef<T>) -> Option<NodeRef<T>> {
         let node = node.borrow().fail.clone()?;
         node.upgrade()
     }
+    // 查找字符串对应的节点
+    fn find<S: AsRef<str>>(node: &NodeRef<T>, s: S) -> Option<NodeRef<T>> {
+        let mut current_node = node.clone();
+        for c in s.as_ref().chars() {
+            let next = current_node.borrow().child.get(&c)?.clone();
+            current_node = next;
+        }
+        Some(current_node)
+    }
     // 匹配字符串
-    pub fn pattern<S: AsRef<str>>(node: &NodeRef, s: S) -> bool {
+    pub fn pattern<S: AsRef<str>>(node: &NodeRef<T>, s: S) -> bool {
         let s = s.as_ref();
         let mut current_node = node.clone();
         for c in s.chars() {
@@ -56,22 +93,131 @@ impl AcNode {
         // 这里的当前节点就是查询到的最后一个节点
         current_node.borrow().is_word
     }
-    pub fn push_str_by_arr<S: AsRef<str>>(node: &NodeRef, vec: Vec<S>) {
+    ///单词的编号和附带的值
+    pub fn get<S: AsRef<str>>(node: &NodeRef<T>, s: S) -> Option<(usize, Option<T>)>
+    where
+        T: Clone,
+    {
+        let node = Self::find(node, s)?;
+        let node = node.borrow();
+        node.is_word.then(|| (node.id, node.value.clone()))
+    }
+    ///插入带编号和值的单词，已经存在的单词会被覆盖，之后`push_str`的编号从更大的编号开始
+    pub fn insert<S: AsRef<str>>(node: &NodeRef<T>, s: S, id: usize, value: Option<T>) {
+        let Some(word) = AcNode::push_path(node, s.as_ref()) else {
+            return;
+        };
+        let mut word = word.borrow_mut();
+        word.is_word = true;
+        word.id = id;
+        word.value = value;
+        let mut root = node.borrow_mut();
+        root.next_id = root.next_id.max(id + 1);
+    }
+    ///删除单词，删除没有其他单词使用的节点，单词不存在时返回false
+    pub fn remove<S: AsRef<str>>(node: &NodeRef<T>, s: S) -> bool {
+        let chars: Vec<char> = s.as_ref().chars().collect();
+        let mut path = vec![node.clone()];
+        for c in &chars {
+            let next = match path[path.len() - 1].borrow().child.get(c) {
+                Some(next) => next.clone(),
+                None => return false,
+            };
+            path.push(next);
+        }
+        {
+            let mut word = path[path.len() - 1].borrow_mut();
+            if chars.is_empty() || !word.is_word {
+                return false;
+            }
+            word.is_word = false;
+            word.value = None;
+        }
+        // 从后往前删除不是单词也没有子节点的节点
+        for i in (1..path.len()).rev() {
+            {
+                let current = path[i].borrow();
+                if current.is_word || !current.child.is_empty() {
+                    break;
+                }
+            }
+            path[i - 1].borrow_mut().child.remove(&chars[i - 1]);
+        }
+        node.borrow_mut().dirty = true;
+        true
+    }
+    ///查找文本中所有的单词，包括重叠的单词，插入或删除之后第一次查找时重新设置fail指针
+    pub fn find_all<S: AsRef<str>>(node: &NodeRef<T>, s: S) -> Vec<Match> {
+        let needs_fail = {
+            let node_ref = node.borrow();
+            node_ref.dirty || node_ref.fail.is_none()
+        };
+        if needs_fail {
+            AcNode::set_fail(node);
+        }
+        let mut matches = Vec::new();
+        let mut current_node = node.clone();
+        for (index, c) in s.as_ref().char_indices() {
+            current_node = AcNode::next_node(node, &current_node, c);
+            AcNode::outputs(&current_node, index + c.len_utf8(), &mut matches);
+        }
+        matches
+    }
+    // 读入一个字符之后的节点，没有对应的子节点时沿着fail链回退
+    fn next_node(head: &NodeRef<T>, node: &NodeRef<T>, c: char) -> NodeRef<T> {
+        let mut current_node = node.clone();
+        loop {
+            let next = current_node.borrow().child.get(&c).cloned();
+            match next {
+                Some(next) => return next,
+                None if Rc::ptr_eq(&current_node, head) => return current_node,
+                None => {
+                    let fail_node = AcNode::get_fail_node(&current_node).expect("不存在fail指针");
+                    current_node = fail_node;
+                }
+            }
+        }
+    }
+    // 当前节点和output链上所有的单词都在end结束
+    fn outputs(node: &NodeRef<T>, end: usize, matches: &mut Vec<Match>) {
+        let mut current_node = match node.borrow().is_word {
+            true => Some(node.clone()),
+            false => AcNode::get_output_node(node),
+        };
+        while let Some(word_node) = current_node {
+            {
+                let node_ref = word_node.borrow();
+                matches.push(Match { pattern: node_ref.id, start: end - node_ref.depth, end });
+            }
+            current_node = AcNode::get_output_node(&word_node);
+        }
+    }
+    pub fn push_str_by_arr<S: AsRef<str>>(node: &NodeRef<T>, vec: Vec<S>) {
         for i in vec {
             AcNode::push_str(node, i);
         }
     }
-    ///插入查找词
-    pub fn push_str<S: AsRef<str>>(node: &NodeRef, s: S) {
-        let s = s.as_ref();
-        let len = s.len();
-        if len == 0 {
+    ///插入查找词，新的单词按插入的顺序编号
+    pub fn push_str<S: AsRef<str>>(node: &NodeRef<T>, s: S) {
+        let Some(word) = AcNode::push_path(node, s.as_ref()) else {
             return;
+        };
+        let mut word = word.borrow_mut();
+        if !word.is_word {
+            let mut root = node.borrow_mut();
+            word.is_word = true;
+            word.id = root.next_id;
+            root.next_id += 1;
+        }
+    }
+    // 插入单词经过的节点，返回最后一个节点，空字符串不插入
+    fn push_path(node: &NodeRef<T>, s: &str) -> Option<NodeRef<T>> {
+        if s.is_empty() {
+            return None;
         }
         // 当前节点等于传入的节点
         let mut current_node = node.clone();
-        let iter = s.chars();
-        for (index, c) in s.chars().enumerate() {
+        for c in s.chars() {
             // 获取下一个节点
             let next_node = {
                 let mut current_borrow = current_node.borrow_mut();
@@ -80,27 +226,31 @@ impl AcNode {
                     existing_node.clone()
                 } else {
                     let new_node = AcNode::new();
+                    new_node.borrow_mut().depth = current_borrow.depth + c.len_utf8();
                     // 如果是最后一个元素那么就添加是个单词
 
                     current_borrow.child.insert(c, new_node.clone());
                     new_node
                 }
             };
-            if len - 1 == index {
-                next_node.borrow_mut().is_word = true;
-            }
             // 将下一个节点的父节点指针指向当前节点
             next_node.borrow_mut().last = Some(Rc::downgrade(&current_node));
             current_node = next_node;
         }
+        node.borrow_mut().dirty = true;
+        Some(current_node)
     }
-    fn set_fail(node: &NodeRef) {
+    fn set_fail(node: &NodeRef<T>) {
         // 将根节点fail;指针设置为自身
-        node.borrow_mut().fail = Some(Rc::downgrade(node));
+        {
+            let mut node_mut = node.borrow_mut();
+            node_mut.fail = Some(Rc::downgrade(node));
+            node_mut.dirty = false;
+        }
         Self::__set_fail(node, node);
     }
 
-    fn __set_fail(node: &NodeRef, head: &NodeRef) {
+    fn __set_fail(node: &NodeRef<T>, head: &NodeRef<T>) {
         // let node_ref=node.borrow();
         let mut deque = VecDeque::new();
         let mut char_deque = VecDeque::new();
@@ -120,27 +270,30 @@ impl AcNode {
             let prev_node = AcNode::get_prev_node(&front_node);
             // 不是头节点的情况
             if let Some(prev_node) = prev_node {
-            let c = char_deque.pop_front().expect("不存在char");
+                let c = char_deque.pop_front().expect("不存在char");
                 // 树中不可能存在不存在fail节点的节点
-                let fail_node = AcNode::get_fail_node(&prev_node).expect("不存在fail指针");
-                // 查看fail指针指向的节点是否有相同但不是自身的节点
-                match fail_node.borrow().child.get(&c) {
-                    Some(find_node) => {
+                let mut fail_node = AcNode::get_fail_node(&prev_node).expect("不存在fail指针");
+                // 沿着fail链查找有相同子节点的节点，按层遍历时fail链上的节点都已经设置好了
+                let target = loop {
+                    let find_node = fail_node.borrow().child.get(&c).cloned();
+                    match find_node {
                         // 如果查找到的节点和当前节点是同一个节点，那么fail指向根节点
-                        if !Rc::ptr_eq(find_node, &front_node) {
-                            // 安全代码
-                            front_node.borrow_mut().fail = Some(Rc::downgrade(find_node))
-                        } else {
-                            front_node.borrow_mut().fail = Some(Rc::downgrade(head))
+                        Some(find_node) if !Rc::ptr_eq(&find_node, &front_node) => break find_node,
+                        _ if Rc::ptr_eq(&fail_node, head) => break head.clone(),
+                        _ => {
+                            let next = AcNode::get_fail_node(&fail_node).expect("不存在fail指针");
+                            fail_node = next;
                         }
                     }
-                    None => {
-                        // 如果fail子节点没有相同的就指向根节点
-                        // 安全代码
-                        // node_ref.fail = Some(Rc::downgrade(node))
-                        front_node.borrow_mut().fail = Some(Rc::downgrade(head));
-                    }
-                }
+                };
+                // fail指向单词时输出它，否则沿用它的output，根节点不是单词
+                let output = match target.borrow().is_word {
+                    true => Some(Rc::downgrade(&target)),
+                    false => target.borrow().output.clone(),
+                };
+                let mut node_mut = front_node.borrow_mut();
+                node_mut.fail = Some(Rc::downgrade(&target));
+                node_mut.output = output;
             }
             // 是头节的情况
             else {
@@ -150,16 +303,853 @@ impl AcNode {
         // 获取头节点
     }
 }
+///一次匹配的结果，`start`和`end`是字节位置，不包含`end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    ///模式在传入的列表中的下标，重复的模式使用第一次出现的下标
+    pub pattern: usize,
+    pub start: usize,
+    pub end: usize,
+}
+///状态在数组中的下标
+type StateId = u32;
+// 根状态，output为ROOT表示没有output
+const ROOT: StateId = 0;
+// 数组中的一个状态，用下标代替指针，没有引用计数和借用检查的开销
+#[derive(Debug, Clone, Default)]
+struct State {
+    // 子状态，按字符排序，二分查找
+    trans: Vec<(char, StateId)>,
+    fail: StateId,
+    // fail链上最近的单词状态
+    output: StateId,
+    // 单词的编号
+    word: Option<u32>,
+    // 从根状态到当前状态的字符数，按折叠之后的字符计算
+    depth: u32,
+}
+impl State {
+    fn child(&self, c: char) -> Option<StateId> {
+        let index = self.trans.binary_search_by_key(&c, |(k, _)| *k).ok()?;
+        Some(self.trans[index].1)
+    }
+}
+// 模式中出现的字符编号从1开始，0表示其他字符
+#[derive(Debug, Clone)]
+struct Alphabet {
+    ascii: [u32; 128],
+    other: HashMap<char, u32>,
+    len: u32,
+}
+impl Alphabet {
+    fn new() -> Self {
+        Self { ascii: [0; 128], other: HashMap::new(), len: 1 }
+    }
+    fn insert(&mut self, c: char) {
+        if self.class(c) != 0 {
+            return;
+        }
+        match c.is_ascii() {
+            true => self.ascii[c as usize] = self.len,
+            false => {
+                self.other.insert(c, self.len);
+            }
+        }
+        self.len += 1;
+    }
+    fn class(&self, c: char) -> u32 {
+        match c.is_ascii() {
+            true => self.ascii[c as usize],
+            false => self.other.get(&c).copied().unwrap_or(0),
+        }
+    }
+}
+// 预先计算的转移表，每个状态占一行，每个字符占一列
+#[derive(Debug, Clone)]
+struct Dfa {
+    alphabet: Alphabet,
+    table: Vec<StateId>,
+}
+///同一段文本中有多个模式重叠时返回哪些匹配
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchKind {
+    ///返回所有的匹配，包括重叠和包含的匹配，按结束位置排列
+    #[default]
+    AllOverlapping,
+    ///不重叠的匹配，从最左边开始的位置中选择在模式列表中最靠前的模式，
+    ///然后从匹配结束的位置继续查找，例如分词
+    LeftmostFirst,
+    ///不重叠的匹配，从最左边开始的位置中选择最长的模式，例如屏蔽敏感词
+    LeftmostLongest,
+}
+// 匹配之前对模式和文本中的字符进行的转换
+#[derive(Debug, Clone, Copy, Default)]
+struct Fold {
+    ascii_case: bool,
+    unicode_case: bool,
+    width: bool,
+}
+impl Fold {
+    fn fold(&self, c: char) -> Folded {
+        let c = match c {
+            // 全角的ASCII字符和全角空格
+            '\u{FF01}'..='\u{FF5E}' if self.width => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            '\u{3000}' if self.width => ' ',
+            _ => c,
+        };
+        if self.unicode_case {
+            // 先转大写再转小写，`ß`和`SS`都得到`ss`，`ſ`和`S`都得到`s`
+            let mut upper = c.to_uppercase();
+            if upper.len() == 1 {
+                let mut lower = upper.next().unwrap_or(c).to_lowercase();
+                if lower.len() == 1 {
+                    return Folded::One(lower.next());
+                }
+            }
+            return Folded::Many(c.to_uppercase().flat_map(char::to_lowercase).collect::<Vec<_>>().into_iter());
+        }
+        match self.ascii_case {
+            true => Folded::One(Some(c.to_ascii_lowercase())),
+            false => Folded::One(Some(c)),
+        }
+    }
+}
+// 一个字符折叠之后的字符，大多数字符只有一个，不需要分配内存
+enum Folded {
+    One(Option<char>),
+    Many(std::vec::IntoIter<char>),
+}
+impl Iterator for Folded {
+    type Item = char;
+    fn next(&mut self) -> Option<char> {
+        match self {
+            Folded::One(c) => c.take(),
+            Folded::Many(iter) => iter.next(),
+        }
+    }
+}
+///创建`AcManger`的选项
+#[derive(Debug, Clone, Default)]
+pub struct AcBuilder {
+    dfa: bool,
+    kind: MatchKind,
+    fold: Fold,
+}
+impl AcBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    ///预先计算每个状态在每个字符上的转移，扫描时不需要回退fail链，
+    ///需要`状态数 * 模式中不同字符数`的内存，适合字符种类少的词典
+    pub fn dfa(mut self, dfa: bool) -> Self {
+        self.dfa = dfa;
+        self
+    }
+    pub fn match_kind(mut self, kind: MatchKind) -> Self {
+        self.kind = kind;
+        self
+    }
+    ///忽略ASCII字母的大小写
+    pub fn ascii_case_insensitive(mut self, yes: bool) -> Self {
+        self.fold.ascii_case = yes;
+        self
+    }
+    ///忽略所有字母的大小写，`straße`可以匹配`STRASSE`，一个字符可能对应多个字符
+    pub fn unicode_case_insensitive(mut self, yes: bool) -> Self {
+        self.fold.unicode_case = yes;
+        self
+    }
+    ///把全角的字母、数字、标点和空格当作半角字符，`ｈｅｌｌｏ`可以匹配`hello`
+    pub fn normalize_width(mut self, yes: bool) -> Self {
+        self.fold.width = yes;
+        self
+    }
+    ///由模式列表创建自动机，空的模式会被忽略，转换之后相同的模式保留第一个的编号
+    pub fn build<S: AsRef<str>>(&self, patterns: &[S]) -> AcManger {
+        let mut states = vec![State::default()];
+        for (id, pattern) in patterns.iter().enumerate() {
+            let mut current = ROOT;
+            for c in pattern.as_ref().chars().flat_map(|c| self.fold.fold(c)) {
+                current = match states[current as usize].child(c) {
+                    Some(next) => next,
+                    None => {
+                        let next = states.len() as StateId;
+                        let depth = states[current as usize].depth + 1;
+                        states.push(State { depth, ..State::default() });
+                        let trans = &mut states[current as usize].trans;
+                        let index = trans.partition_point(|(k, _)| *k < c);
+                        trans.insert(index, (c, next));
+                        next
+                    }
+                };
+            }
+            // 重复的模式保留第一次的编号
+            let state = &mut states[current as usize];
+            if current != ROOT && state.word.is_none() {
+                state.word = Some(id as u32);
+            }
+        }
+        set_fail(&mut states);
+        let dfa = self.dfa.then(|| build_dfa(&states));
+        let max_depth = states.iter().map(|i| i.depth).max().unwrap_or(0) as usize;
+        AcManger {
+            states,
+            dfa,
+            kind: self.kind,
+            fold: self.fold,
+            max_depth,
+            patterns: patterns.len(),
+            searcher: Searcher::default(),
+        }
+    }
+}
+// 按层遍历设置fail和output，父状态的fail链上的状态都已经设置好了
+fn set_fail(states: &mut [State]) {
+    let mut deque: VecDeque<StateId> = states[ROOT as usize].trans.iter().map(|(_, s)| *s).collect();
+    while let Some(parent) = deque.pop_front() {
+        for (c, child) in states[parent as usize].trans.clone() {
+            deque.push_back(child);
+            let mut fail = states[parent as usize].fail;
+            let target = loop {
+                match states[fail as usize].child(c) {
+                    Some(next) => break next,
+                    None if fail == ROOT => break ROOT,
+                    None => fail = states[fail as usize].fail,
+                }
+            };
+            let output = match states[target as usize].word {
+                Some(_) => target,
+                None => states[target as usize].output,
+            };
+            let state = &mut states[child as usize];
+            state.fail = target;
+            state.output = output;
+        }
+    }
+}
+// 每一行由fail状态的行得到，按层遍历保证fail状态的行已经计算好了
+fn build_dfa(states: &[State]) -> Dfa {
+    let mut alphabet = Alphabet::new();
+    for state in states {
+        state.trans.iter().for_each(|(c, _)| alphabet.insert(*c));
+    }
+    let width = alphabet.len as usize;
+    let mut table = vec![ROOT; states.len() * width];
+    let mut deque = VecDeque::from([ROOT]);
+    while let Some(id) = deque.pop_front() {
+        let state = &states[id as usize];
+        let row = id as usize * width;
+        if id != ROOT {
+            let fail_row = state.fail as usize * width;
+            table.copy_within(fail_row..fail_row + width, row);
+        }
+        for (c, child) in &state.trans {
+            table[row + alphabet.class(*c) as usize] = *child;
+            deque.push_back(*child);
+        }
+    }
+    Dfa { alphabet, table }
+}
+///多模式匹配，一次扫描找出所有模式出现的位置，`MatchKind`决定返回哪些匹配
+#[derive(Debug, Clone)]
 pub struct AcManger {
-    current_node: NodeRef,
-    head: NodeRef,
+    states: Vec<State>,
+    dfa: Option<Dfa>,
+    kind: MatchKind,
+    fold: Fold,
+    // 最长的模式的字符数
+    max_depth: usize,
+    // 模式的个数，包括跳过的空模式
+    patterns: usize,
+    // `feed`的状态
+    searcher: Searcher,
+}
+impl AcManger {
+    ///使用默认选项创建自动机
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        AcBuilder::new().build(patterns)
+    }
+    ///读入一个字符，返回已经确定的匹配，位置从第一次`feed`开始计算。
+    ///`AllOverlapping`返回在这个字符结束的匹配，其他方式的匹配要等后面的字符确定之后才返回
+    pub fn feed(&mut self, c: char) -> Vec<Match> {
+        let mut searcher = std::mem::take(&mut self.searcher);
+        let mut matches = Vec::new();
+        searcher.push(self, c, &mut matches);
+        self.searcher = searcher;
+        matches
+    }
+    ///输入结束，返回还没有确定的匹配，之后可以重新开始`feed`
+    pub fn finish(&mut self) -> Vec<Match> {
+        let mut searcher = std::mem::take(&mut self.searcher);
+        let mut matches = Vec::new();
+        searcher.finish(self, &mut matches);
+        matches
+    }
+    ///回到初始状态，丢弃没有确定的匹配，重新开始计算位置
+    pub fn reset(&mut self) {
+        self.searcher = Searcher::default();
+    }
+    ///状态的个数，包括根状态
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+    ///模式的个数，替换时需要相同个数的字符串
+    pub fn patterns(&self) -> usize {
+        self.patterns
+    }
+    ///查找字符串中所有的匹配，按`MatchKind`选择，不影响`feed`的状态
+    pub fn find_iter<'a>(&'a self, s: &'a str) -> FindIter<'a> {
+        FindIter { ac: self, searcher: Searcher::default(), chars: s.chars(), pending: VecDeque::new(), finished: false }
+    }
+    ///把匹配到的模式替换为`replacements`中相同下标的字符串，只扫描一遍。
+    ///`replacements`的长度和模式个数不同时返回错误，`AllOverlapping`时跳过和前面的匹配重叠的匹配
+    pub fn replace_all<S: AsRef<str>>(&self, text: &str, replacements: &[S]) -> Result<String, ReplaceError> {
+        self.check_replacements(replacements)?;
+        let mut result = String::with_capacity(text.len());
+        let mut last_end = 0;
+        for m in self.find_iter(text) {
+            if m.start < last_end {
+                continue;
+            }
+            result.push_str(&text[last_end..m.start]);
+            result.push_str(replacements[m.pattern].as_ref());
+            last_end = m.end;
+        }
+        result.push_str(&text[last_end..]);
+        Ok(result)
+    }
+    fn check_replacements<S>(&self, replacements: &[S]) -> Result<(), ReplaceError> {
+        if replacements.len() != self.patterns {
+            return Err(ReplaceError { expected: self.patterns, found: replacements.len() });
+        }
+        Ok(())
+    }
+    ///从`reader`读入文本，替换之后写入`writer`，不需要把全部文本读入内存
+    pub fn redact<R: Read, W: Write, S: AsRef<str>>(&self, mut reader: R, writer: W, replacements: &[S]) -> io::Result<W> {
+        let mut redactor = Redactor::new(self, writer, replacements)?;
+        io::copy(&mut reader, &mut redactor)?;
+        redactor.finish()
+    }
+}
+const AC_MAGIC: [u8; 4] = *b"ACMG";
+// 每个状态保存6个u32：转移的开始位置、转移个数、fail、output、单词编号、深度
+const STATE_WORDS: usize = 6;
+const NO_WORD: u32 = u32::MAX;
+impl AcManger {
+    ///保存为二进制格式，可以用`AcView::new`直接在字节上查找，或者用`from_bytes`恢复，
+    ///DFA的转移表不保存，恢复时重新计算
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let kind = match self.kind {
+            MatchKind::AllOverlapping => 0,
+            MatchKind::LeftmostFirst => 1,
+            MatchKind::LeftmostLongest => 2,
+        };
+        let fold = self.fold.ascii_case as u8 | (self.fold.unicode_case as u8) << 1 | (self.fold.width as u8) << 2;
+        let mut bytes = vec![kind, fold, self.dfa.is_some() as u8, 0];
+        let trans_count: usize = self.states.iter().map(|i| i.trans.len()).sum();
+        for value in [self.max_depth, self.patterns, self.states.len(), trans_count] {
+            put_u32(&mut bytes, value as u32);
+        }
+        let mut start = 0;
+        for state in &self.states {
+            let word = state.word.unwrap_or(NO_WORD);
+            for value in [start, state.trans.len() as u32, state.fail, state.output, word, state.depth] {
+                put_u32(&mut bytes, value);
+            }
+            start += state.trans.len() as u32;
+        }
+        for (c, next) in self.states.iter().flat_map(|i| i.trans.iter()) {
+            put_u32(&mut bytes, *c as u32);
+            put_u32(&mut bytes, *next);
+        }
+        serial::seal(AC_MAGIC, bytes)
+    }
+    ///读取`to_bytes`保存的自动机，复制到内存中
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SerialError> {
+        let view = AcView::new(bytes)?;
+        let states: Vec<State> = (0..view.len() as StateId)
+            .map(|id| State {
+                trans: view.trans(id).map(|i| (char::from_u32(view.trans_char(i)).unwrap_or('\0'), view.trans_next(i))).collect(),
+                fail: view.fail(id),
+                output: view.output(id),
+                word: view.word(id),
+                depth: view.depth(id),
+            })
+            .collect();
+        let dfa = view.dfa.then(|| build_dfa(&states));
+        Ok(AcManger {
+            states,
+            dfa,
+            kind: view.kind,
+            fold: view.fold,
+            max_depth: view.max_depth,
+            patterns: view.patterns,
+            searcher: Searcher::default(),
+        })
+    }
+}
+///直接在`to_bytes`得到的字节上查找，不复制数据，字节可以来自内存映射的文件
+#[derive(Debug, Clone)]
+pub struct AcView<'a> {
+    kind: MatchKind,
+    fold: Fold,
+    dfa: bool,
+    max_depth: usize,
+    patterns: usize,
+    states: &'a [u8],
+    trans: &'a [u8],
+}
+impl<'a> AcView<'a> {
+    ///检查头部、校验和以及所有的下标，之后查找时不会越界
+    pub fn new(bytes: &'a [u8]) -> Result<Self, SerialError> {
+        let mut reader = Reader::new(serial::open(AC_MAGIC, bytes)?);
+        let kind = match reader.u8()? {
+            0 => MatchKind::AllOverlapping,
+            1 => MatchKind::LeftmostFirst,
+            2 => MatchKind::LeftmostLongest,
+            _ => return Err(SerialError::Corrupted("unknown match kind")),
+        };
+        let flags = reader.u8()?;
+        let fold = Fold { ascii_case: flags & 1 != 0, unicode_case: flags & 2 != 0, width: flags & 4 != 0 };
+        let dfa = reader.u8()? != 0;
+        reader.u8()?;
+        let max_depth = reader.u32()? as usize;
+        let patterns = reader.u32()? as usize;
+        let state_count = reader.u32()? as usize;
+        let trans_count = reader.u32()? as usize;
+        let states = reader.slice(state_count.checked_mul(STATE_WORDS * 4).ok_or(SerialError::Truncated)?)?;
+        let trans = reader.slice(trans_count.checked_mul(8).ok_or(SerialError::Truncated)?)?;
+        let view = AcView { kind, fold, dfa, max_depth, patterns, states, trans };
+        view.validate(trans_count)?;
+        Ok(view)
+    }
+    // 下标都在范围内，转移按字符排序，子状态比父状态深一层，fail和output比当前状态浅，
+    // 根状态的fail和output指向自己并且没有模式，否则查找时沿着fail回退不会停止
+    fn validate(&self, trans_count: usize) -> Result<(), SerialError> {
+        let corrupted = Err(SerialError::Corrupted("invalid automaton"));
+        let len = self.len() as u32;
+        if len == 0 || self.depth(ROOT) != 0 {
+            return corrupted;
+        }
+        if self.fail(ROOT) != ROOT || self.output(ROOT) != ROOT || self.word(ROOT).is_some() {
+            return corrupted;
+        }
+        for id in 0..len {
+            let start = u32_at(self.states, id as usize * STATE_WORDS) as usize;
+            let count = u32_at(self.states, id as usize * STATE_WORDS + 1) as usize;
+            let depth = self.depth(id);
+            if start.checked_add(count).is_none_or(|end| end > trans_count) || depth as usize > self.max_depth {
+                return corrupted;
+            }
+            let (fail, output) = (self.fail(id), self.output(id));
+            if id != ROOT && (fail >= len || output >= len || self.depth(fail) >= depth || self.depth(output) >= depth) {
+                return corrupted;
+            }
+            if self.word(id).is_some_and(|word| word as usize >= self.patterns) {
+                return corrupted;
+            }
+            let mut last = None;
+            for i in self.trans(id) {
+                let (c, next) = (self.trans_char(i), self.trans_next(i));
+                if char::from_u32(c).is_none() || last.is_some_and(|last| last >= c) || next == ROOT || next >= len {
+                    return corrupted;
+                }
+                if self.depth(next) != depth + 1 {
+                    return corrupted;
+                }
+                last = Some(c);
+            }
+        }
+        Ok(())
+    }
+    ///状态的个数，包括根状态
+    pub fn len(&self) -> usize {
+        self.states.len() / (STATE_WORDS * 4)
+    }
+    ///查找字符串中所有的匹配，和保存之前的`AcManger::find_iter`结果相同
+    pub fn find_iter<'s>(&'s self, s: &'s str) -> FindIter<'s> {
+        FindIter { ac: self, searcher: Searcher::default(), chars: s.chars(), pending: VecDeque::new(), finished: false }
+    }
+    fn field(&self, state: StateId, index: usize) -> u32 {
+        u32_at(self.states, state as usize * STATE_WORDS + index)
+    }
+    // 状态的转移在转移数组中的下标
+    fn trans(&self, state: StateId) -> std::ops::Range<usize> {
+        let start = self.field(state, 0) as usize;
+        start..start + self.field(state, 1) as usize
+    }
+    fn trans_char(&self, index: usize) -> u32 {
+        u32_at(self.trans, index * 2)
+    }
+    fn trans_next(&self, index: usize) -> StateId {
+        u32_at(self.trans, index * 2 + 1)
+    }
+}
+impl Automaton for AcView<'_> {
+    fn kind(&self) -> MatchKind {
+        self.kind
+    }
+    fn fold(&self) -> Fold {
+        self.fold
+    }
+    fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+    fn child(&self, state: StateId, c: char) -> Option<StateId> {
+        let range = self.trans(state);
+        let (mut low, mut high) = (range.start, range.end);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.trans_char(mid).cmp(&(c as u32)) {
+                std::cmp::Ordering::Equal => return Some(self.trans_next(mid)),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        None
+    }
+    fn fail(&self, state: StateId) -> StateId {
+        self.field(state, 2)
+    }
+    fn output(&self, state: StateId) -> StateId {
+        self.field(state, 3)
+    }
+    fn word(&self, state: StateId) -> Option<u32> {
+        Some(self.field(state, 4)).filter(|id| *id != NO_WORD)
+    }
+    fn depth(&self, state: StateId) -> u32 {
+        self.field(state, 5)
+    }
+}
+// 查找时需要的操作，`AcManger`和直接读取字节的`AcView`都实现
+trait Automaton {
+    fn kind(&self) -> MatchKind;
+    fn fold(&self) -> Fold;
+    // 最长的模式的字符数
+    fn max_depth(&self) -> usize;
+    fn child(&self, state: StateId, c: char) -> Option<StateId>;
+    fn fail(&self, state: StateId) -> StateId;
+    fn output(&self, state: StateId) -> StateId;
+    fn word(&self, state: StateId) -> Option<u32>;
+    fn depth(&self, state: StateId) -> u32;
+    // 读入一个字符之后的状态，没有对应的子状态时沿着fail链回退
+    fn next_state(&self, mut state: StateId, c: char) -> StateId {
+        loop {
+            match self.child(state, c) {
+                Some(next) => return next,
+                None if state == ROOT => return ROOT,
+                None => state = self.fail(state),
+            }
+        }
+    }
+    // 当前状态和output链上所有的单词都在end结束，开始位置由最近读入的字符在原文中的位置得到
+    fn outputs(&self, state: StateId, end: usize, history: &VecDeque<usize>, matches: &mut Vec<Match>) {
+        let mut current = match self.word(state) {
+            Some(_) => state,
+            None => self.output(state),
+        };
+        while current != ROOT {
+            if let Some(id) = self.word(current) {
+                let start = history[history.len() - self.depth(current) as usize];
+                matches.push(Match { pattern: id as usize, start, end });
+            }
+            current = self.output(current);
+        }
+    }
+}
+impl Automaton for AcManger {
+    fn kind(&self) -> MatchKind {
+        self.kind
+    }
+    fn fold(&self) -> Fold {
+        self.fold
+    }
+    fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+    fn child(&self, state: StateId, c: char) -> Option<StateId> {
+        self.states[state as usize].child(c)
+    }
+    fn fail(&self, state: StateId) -> StateId {
+        self.states[state as usize].fail
+    }
+    fn output(&self, state: StateId) -> StateId {
+        self.states[state as usize].output
+    }
+    fn word(&self, state: StateId) -> Option<u32> {
+        self.states[state as usize].word
+    }
+    fn depth(&self, state: StateId) -> u32 {
+        self.states[state as usize].depth
+    }
+    fn next_state(&self, mut state: StateId, c: char) -> StateId {
+        if let Some(dfa) = &self.dfa {
+            let width = dfa.alphabet.len as usize;
+            return dfa.table[state as usize * width + dfa.alphabet.class(c) as usize];
+        }
+        loop {
+            match self.states[state as usize].child(c) {
+                Some(next) => return next,
+                None if state == ROOT => return ROOT,
+                None => state = self.states[state as usize].fail,
+            }
+        }
+    }
+}
+// 逐个字符查找，保存左边优先时还没有确定的匹配
+#[derive(Debug, Clone, Default)]
+struct Searcher {
+    state: StateId,
+    // 已经读入的字节数
+    offset: usize,
+    // 最近读入的折叠之后的字符在原文中的开始位置，最多保留最长的模式的字符数
+    history: VecDeque<usize>,
+    // 当前最好的匹配，后面不会再出现开始位置不大于它的匹配时才确定
+    candidate: Option<Match>,
+    // 确定匹配之后要从匹配的结束位置重新查找，保留可能需要重新查找的原文中的字符
+    buffer: VecDeque<(usize, char)>,
+}
+impl Searcher {
+    fn push(&mut self, ac: &dyn Automaton, c: char, out: &mut Vec<Match>) {
+        let offset = self.offset;
+        self.offset += c.len_utf8();
+        self.run(ac, VecDeque::from([(offset, c)]), out);
+    }
+    fn run(&mut self, ac: &dyn Automaton, mut queue: VecDeque<(usize, char)>, out: &mut Vec<Match>) {
+        while let Some((offset, c)) = queue.pop_front() {
+            if ac.kind() != MatchKind::AllOverlapping {
+                self.buffer.push_back((offset, c));
+            }
+            for folded in ac.fold().fold(c) {
+                // 确定匹配之后这个字符剩下的部分在重新查找时处理
+                if self.step(ac, offset, offset + c.len_utf8(), folded, out, &mut queue) {
+                    break;
+                }
+            }
+        }
+    }
+    // 读入一个折叠之后的字符，确定了匹配时返回true
+    fn step(
+        &mut self,
+        ac: &dyn Automaton,
+        offset: usize,
+        end: usize,
+        c: char,
+        out: &mut Vec<Match>,
+        queue: &mut VecDeque<(usize, char)>,
+    ) -> bool {
+        self.state = ac.next_state(self.state, c);
+        self.history.push_back(offset);
+        if self.history.len() > ac.max_depth() {
+            self.history.pop_front();
+        }
+        let mut matches = Vec::new();
+        ac.outputs(self.state, end, &self.history, &mut matches);
+        if ac.kind() == MatchKind::AllOverlapping {
+            out.extend(matches);
+            return false;
+        }
+        for m in matches {
+            if self.candidate.is_none_or(|candidate| better(ac.kind(), &m, &candidate)) {
+                self.candidate = Some(m);
+            }
+        }
+        // 以后的匹配至少从这里开始，字符折叠成多个字符时剩下的字符可能从这个字符开始
+        let earliest = self.earliest(ac, offset);
+        match self.candidate {
+            Some(candidate) if earliest > candidate.start => {
+                self.commit(candidate, out, queue);
+                return true;
+            }
+            Some(candidate) => self.trim(candidate.start),
+            None => self.trim(earliest),
+        }
+        false
+    }
+    // 当前状态对应的文本的开始位置，没有读入任何模式的前缀时返回current
+    fn earliest(&self, ac: &dyn Automaton, current: usize) -> usize {
+        match ac.depth(self.state) as usize {
+            0 => current,
+            depth => self.history[self.history.len() - depth],
+        }
+    }
+    // 输出匹配，从匹配结束的位置重新查找，重新查找的字符放在还没有读入的字符前面
+    fn commit(&mut self, candidate: Match, out: &mut Vec<Match>, queue: &mut VecDeque<(usize, char)>) {
+        out.push(candidate);
+        self.candidate = None;
+        self.state = ROOT;
+        self.history.clear();
+        let rest: Vec<_> = self.buffer.drain(..).filter(|(offset, _)| *offset >= candidate.end).collect();
+        for item in rest.into_iter().rev() {
+            queue.push_front(item);
+        }
+    }
+    // 以后确定的匹配不会在这个位置之前开始，之前的文本可以输出
+    fn safe_point(&self, ac: &dyn Automaton) -> usize {
+        let earliest = self.earliest(ac, self.offset);
+        match self.candidate {
+            Some(candidate) => candidate.start.min(earliest),
+            None => earliest,
+        }
+    }
+    fn trim(&mut self, start: usize) {
+        while self.buffer.front().is_some_and(|(offset, _)| *offset < start) {
+            self.buffer.pop_front();
+        }
+    }
+    fn finish(&mut self, ac: &dyn Automaton, out: &mut Vec<Match>) {
+        while let Some(candidate) = self.candidate {
+            let mut queue = VecDeque::new();
+            self.commit(candidate, out, &mut queue);
+            self.run(ac, queue, out);
+        }
+        *self = Searcher::default();
+    }
+}
+// 开始位置小的优先，开始位置相同时按方式选择
+fn better(kind: MatchKind, m: &Match, candidate: &Match) -> bool {
+    match m.start.cmp(&candidate.start) {
+        std::cmp::Ordering::Less => true,
+        std::cmp::Ordering::Greater => false,
+        std::cmp::Ordering::Equal => match kind {
+            MatchKind::LeftmostLongest => m.end > candidate.end,
+            _ => m.pattern < candidate.pattern,
+        },
+    }
+}
+///替换的字符串个数和模式个数不同
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Expected {expected} replacements, got {found}")]
+pub struct ReplaceError {
+    pub expected: usize,
+    pub found: usize,
+}
+///写入的文本替换之后再写入内部的`Write`，文本可以在任意位置分块写入，包括字符的中间。
+///可能是匹配的一部分的文本会保留到确定之后再写入，最后必须调用`finish`
+pub struct Redactor<'a, W: Write, S: AsRef<str>> {
+    ac: &'a AcManger,
+    replacements: &'a [S],
+    inner: W,
+    searcher: Searcher,
+    // 上一次写入时末尾不完整的UTF-8字节
+    partial: Vec<u8>,
+    // 还没有写入inner的文本，从base位置开始
+    text: String,
+    base: usize,
+    // 上一个替换的结束位置，跳过和它重叠的匹配
+    last_end: usize,
+}
+impl<'a, W: Write, S: AsRef<str>> Redactor<'a, W, S> {
+    ///`replacements`的个数和模式个数不同时返回`InvalidInput`，写入时不会因为缺少替换而失败
+    pub fn new(ac: &'a AcManger, inner: W, replacements: &'a [S]) -> io::Result<Self> {
+        ac.check_replacements(replacements).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        Ok(Self {
+            ac,
+            replacements,
+            inner,
+            searcher: Searcher::default(),
+            partial: Vec::new(),
+            text: String::new(),
+            base: 0,
+            last_end: 0,
+        })
+    }
+    // 输出到pos之前的文本
+    fn flush_to(&mut self, pos: usize) -> io::Result<()> {
+        if pos > self.base {
+            self.inner.write_all(&self.text.as_bytes()[..pos - self.base])?;
+            self.text.drain(..pos - self.base);
+            self.base = pos;
+        }
+        Ok(())
+    }
+    fn apply(&mut self, matches: Vec<Match>) -> io::Result<()> {
+        for m in matches {
+            if m.start < self.last_end {
+                continue;
+            }
+            self.flush_to(m.start)?;
+            self.inner.write_all(self.replacements[m.pattern].as_ref().as_bytes())?;
+            self.text.drain(..m.end - self.base);
+            self.base = m.end;
+            self.last_end = m.end;
+        }
+        Ok(())
+    }
+    ///处理剩下的文本，返回内部的`Write`，输入在字符中间结束时返回`InvalidData`
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut matches = Vec::new();
+        self.searcher.finish(self.ac, &mut matches);
+        self.apply(matches)?;
+        self.flush_to(self.base + self.text.len())?;
+        if !self.partial.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "incomplete utf-8 sequence"));
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+impl<W: Write, S: AsRef<str>> Write for Redactor<'_, W, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(s) => s.len(),
+            // 末尾的字符不完整时留到下一次
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+        let bytes: Vec<u8> = self.partial.drain(..valid).collect();
+        let chunk = std::str::from_utf8(&bytes).expect("已经检查过");
+        let mut matches = Vec::new();
+        for c in chunk.chars() {
+            self.text.push(c);
+            self.searcher.push(self.ac, c, &mut matches);
+        }
+        self.apply(matches)?;
+        self.flush_to(self.searcher.safe_point(self.ac))?;
+        Ok(buf.len())
+    }
+    ///只刷新内部的`Write`，可能是匹配的一部分的文本不会写入
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+///`find_iter`返回的迭代器
+pub struct FindIter<'a> {
+    ac: &'a dyn Automaton,
+    searcher: Searcher,
+    chars: std::str::Chars<'a>,
+    // 已经确定的匹配
+    pending: VecDeque<Match>,
+    finished: bool,
+}
+impl Iterator for FindIter<'_> {
+    type Item = Match;
+    fn next(&mut self) -> Option<Match> {
+        loop {
+            if let Some(m) = self.pending.pop_front() {
+                return Some(m);
+            }
+            if self.finished {
+                return None;
+            }
+            let mut matches = Vec::new();
+            match self.chars.next() {
+                Some(c) => self.searcher.push(self.ac, c, &mut matches),
+                None => {
+                    self.searcher.finish(self.ac, &mut matches);
+                    self.finished = true;
+                }
+            }
+            self.pending.extend(matches);
+        }
+    }
 }
-impl AcManger {}
 
 // 使用示例
 #[test]
 fn test() {
-    let root = AcNode::new();
+    let root = AcNode::<()>::new();
     AcNode::push_str(&root, "her");
     AcNode::push_str(&root, "his");
     AcNode::push_str(&root, "he");
@@ -178,8 +1168,257 @@ fn test() {
 }
 
 #[test]
-fn struct_sample_test() {
+fn find_iter_test() {
+    let ac = AcManger::new(&["he", "she", "his", "hers", "she"]);
+    let found: Vec<_> = ac.find_iter("ushers").map(|m| (m.pattern, m.start, m.end)).collect();
+    assert_eq!(vec![(1, 1, 4), (0, 2, 4), (3, 2, 6)], found);
+    // fail链需要回退多层，`abcd`失败之后要回到`bcd`再到`cd`
+    let ac = AcManger::new(&["abcx", "bcdy", "cde"]);
+    let found: Vec<_> = ac.find_iter("abcde").map(|m| (m.pattern, m.start, m.end)).collect();
+    assert_eq!(vec![(2, 2, 5)], found);
+    // 位置是字节位置，可以直接截取原字符串
+    let text = "日志：错误和警告";
+    let ac = AcManger::new(&["错误", "警告", ""]);
+    let words: Vec<_> = ac.find_iter(text).map(|m| &text[m.start..m.end]).collect();
+    assert_eq!(vec!["错误", "警告"], words);
+}
+#[test]
+fn output_link_test() {
+    let root = AcNode::<()>::new();
+    AcNode::push_str_by_arr(&root, vec!["she", "he", "e", "hex"]);
+    AcNode::set_fail(&root);
+    // `she`的output是`he`，`he`的output是`e`
+    let she = AcNode::find(&root, "she").unwrap();
+    let he = AcNode::get_output_node(&she).unwrap();
+    assert_eq!(2, he.borrow().depth);
+    assert_eq!(1, AcNode::get_output_node(&he).unwrap().borrow().depth);
+    // `sh`的fail是`h`，不是单词，output为空
+    assert!(AcNode::get_output_node(&AcNode::find(&root, "sh").unwrap()).is_none());
+    // 重叠的匹配全部输出
+    let ac = AcManger::new(&["a", "aa", "aaa"]);
+    assert_eq!(9, ac.find_iter("aaaa").count());
+    let ac = AcManger::new(&["she", "he", "e"]);
+    let found: Vec<_> = ac.find_iter("she").map(|m| m.pattern).collect();
+    assert_eq!(vec![0, 1, 2], found);
+}
+#[test]
+fn payload_test() {
+    use crate::token::Expr;
     let root = AcNode::new();
+    AcNode::insert(&root, "he", 1, Some(Expr::Number(10)));
+    AcNode::insert(&root, "she", 2, None);
+    AcNode::insert(&root, "hers", 3, Some(Expr::Symbol("h".to_string())));
+    assert!(matches!(AcNode::get(&root, "he"), Some((1, Some(Expr::Number(10))))));
+    assert!(AcNode::get(&root, "sh").is_none());
+    let found: Vec<_> = AcNode::find_all(&root, "ushers").iter().map(|m| (m.pattern, m.start, m.end)).collect();
+    assert_eq!(vec![(2, 1, 4), (1, 2, 4), (3, 2, 6)], found);
+    // 删除之后下一次查找重新设置fail指针，被删除的节点不会留下失效的指针
+    assert!(AcNode::remove(&root, "she"));
+    assert!(!AcNode::remove(&root, "she"));
+    assert!(!AcNode::remove(&root, "h"));
+    assert!(AcNode::find(&root, "s").is_none());
+    let found: Vec<_> = AcNode::find_all(&root, "ushers").iter().map(|m| m.pattern).collect();
+    assert_eq!(vec![1, 3], found);
+    // 删除前缀单词时保留更长的单词
+    assert!(AcNode::remove(&root, "he"));
+    assert!(AcNode::pattern(&root, "hers"));
+    AcNode::insert(&root, "rs", 4, None);
+    let found: Vec<_> = AcNode::find_all(&root, "hers").iter().map(|m| m.pattern).collect();
+    assert_eq!(vec![3, 4], found);
+}
+#[test]
+fn push_str_id_test() {
+    let root = AcNode::<()>::new();
+    AcNode::push_str_by_arr(&root, vec!["she", "he", "she", "hers"]);
+    // 重复的单词保留第一次的编号
+    assert_eq!(Some((0, None)), AcNode::get(&root, "she"));
+    assert_eq!(Some((2, None)), AcNode::get(&root, "hers"));
+    let found: Vec<_> = AcNode::find_all(&root, "ushers").iter().map(|m| m.pattern).collect();
+    assert_eq!(vec![0, 1, 2], found);
+    // 显式编号之后的单词从更大的编号开始，不会和已有的编号相同
+    AcNode::insert(&root, "us", 10, Some(()));
+    AcNode::push_str(&root, "u");
+    assert_eq!(Some((11, None)), AcNode::get(&root, "u"));
+}
+#[test]
+fn feed_test() {
+    let mut ac = AcManger::new(&["ab", "b", "bc"]);
+    assert!(ac.feed('a').is_empty());
+    assert_eq!(vec![Match { pattern: 0, start: 0, end: 2 }, Match { pattern: 1, start: 1, end: 2 }], ac.feed('b'));
+    // 分块读入时位置接着上一次计算
+    assert_eq!(vec![Match { pattern: 2, start: 1, end: 3 }], ac.feed('c'));
+    ac.reset();
+    assert_eq!(vec![Match { pattern: 1, start: 0, end: 1 }], ac.feed('b'));
+}
+
+#[test]
+fn dfa_test() {
+    let patterns = ["he", "she", "his", "hers", "中文", "文字"];
+    let text = "ushers说中文字his";
+    let sparse = AcManger::new(&patterns);
+    let dense = AcBuilder::new().dfa(true).build(&patterns);
+    let expected: Vec<_> = sparse.find_iter(text).collect();
+    assert_eq!(expected, dense.find_iter(text).collect::<Vec<_>>());
+    assert_eq!(6, expected.len());
+    // 根、h、he、her、hers、hi、his、s、sh、she、中、中文、文、文字，相同的前缀共享状态
+    assert_eq!(14, sparse.len());
+}
+#[test]
+fn match_kind_test() {
+    let patterns = ["sam", "samwise", "wise", "is"];
+    let find = |kind, text| {
+        let ac = AcBuilder::new().match_kind(kind).build(&patterns);
+        ac.find_iter(text).map(|m| &text[m.start..m.end]).collect::<Vec<_>>()
+    };
+    assert_eq!(vec!["sam", "is", "samwise", "wise"], find(MatchKind::AllOverlapping, "samwise"));
+    // 相同位置开始时选择列表中靠前的`sam`，之后从`wise`开始
+    assert_eq!(vec!["sam", "wise"], find(MatchKind::LeftmostFirst, "samwise"));
+    assert_eq!(vec!["samwise"], find(MatchKind::LeftmostLongest, "samwise"));
+    // 开始位置更靠左的匹配优先，即使更短的匹配先结束
+    let ac = AcBuilder::new().match_kind(MatchKind::LeftmostLongest).build(&["bcd", "abcde", "e"]);
+    let found: Vec<_> = ac.find_iter("xabcdfe").map(|m| m.pattern).collect();
+    assert_eq!(vec![0, 2], found);
+    // 没有匹配完的长模式不影响前面的匹配
+    let ac = AcBuilder::new().match_kind(MatchKind::LeftmostFirst).build(&["abcdef", "ab", "cd"]);
+    let found: Vec<_> = ac.find_iter("abcdex").map(|m| (m.pattern, m.start)).collect();
+    assert_eq!(vec![(1, 0), (2, 2)], found);
+    let found: Vec<_> = ac.find_iter("abcdef").map(|m| (m.pattern, m.start)).collect();
+    assert_eq!(vec![(0, 0)], found);
+}
+#[test]
+fn leftmost_feed_test() {
+    let mut ac = AcBuilder::new().match_kind(MatchKind::LeftmostLongest).dfa(true).build(&["ab", "abcd"]);
+    assert!(ac.feed('a').is_empty());
+    // `ab`要等到确定不是`abcd`之后才返回
+    assert!(ac.feed('b').is_empty());
+    assert!(ac.feed('c').is_empty());
+    assert_eq!(vec![Match { pattern: 0, start: 0, end: 2 }], ac.feed('x'));
+    ac.feed('a');
+    ac.feed('b');
+    assert_eq!(vec![Match { pattern: 0, start: 4, end: 6 }], ac.finish());
+}
+#[test]
+fn replace_test() {
+    let ac = AcBuilder::new().match_kind(MatchKind::LeftmostLongest).build(&["坏", "坏人", "bad"]);
+    assert_eq!("*是**,not ***", ac.replace_all("坏是坏人,not bad", &["*", "**", "***"]).unwrap());
+    // 重叠的匹配只替换第一个
+    let ac = AcManger::new(&["abc", "bcd"]);
+    assert_eq!("1d", ac.replace_all("abcd", &["1", "2"]).unwrap());
+    // 替换的个数必须和模式个数相同，包括空模式
+    assert_eq!(Err(ReplaceError { expected: 2, found: 1 }), ac.replace_all("abcd", &["1"]));
+    let ac = AcManger::new(&["", "b"]);
+    assert_eq!("a2", ac.replace_all("ab", &["1", "2"]).unwrap());
+}
+#[test]
+fn redact_test() {
+    let ac = AcBuilder::new().match_kind(MatchKind::LeftmostLongest).build(&["密码", "密码是", "secret"]);
+    let text = "用户的密码是secret,密码错误";
+    let replacements = ["[1]", "[2]", "[3]"];
+    let expected = ac.replace_all(text, &replacements).unwrap();
+    assert_eq!("用户的[2][3],[1]错误", expected);
+    // 每次只写入一个字节，字符和模式都被分开
+    let mut redactor = Redactor::new(&ac, Vec::new(), &replacements).unwrap();
+    for b in text.bytes() {
+        redactor.write_all(&[b]).unwrap();
+    }
+    assert_eq!(expected.as_bytes(), redactor.finish().unwrap().as_slice());
+    let out = ac.redact(text.as_bytes(), Vec::new(), &replacements).unwrap();
+    assert_eq!(expected.as_bytes(), out.as_slice());
+    // 没有匹配的文本及时写入，不会全部保留到最后
+    let mut redactor = Redactor::new(&ac, Vec::new(), &replacements).unwrap();
+    redactor.write_all("abc密".as_bytes()).unwrap();
+    assert_eq!(b"abc", redactor.inner.as_slice());
+    let err = ac.redact(&[0xe5, 0xaf][..], Vec::new(), &replacements).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    // 缺少替换时在写入之前失败
+    let err = Redactor::new(&ac, Vec::new(), &replacements[..2]).err().unwrap();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    assert!(ac.redact("密码".as_bytes(), Vec::new(), &replacements[..2]).is_err());
+}
+#[test]
+fn fold_test() {
+    let find = |ac: &AcManger, text: &'static str| ac.find_iter(text).map(|m| &text[m.start..m.end]).collect::<Vec<_>>();
+    let ac = AcBuilder::new().ascii_case_insensitive(true).build(&["Hello"]);
+    assert_eq!(vec!["HELLO", "hello"], find(&ac, "HELLO, hello"));
+    // 全角字符占3个字节，位置仍然是原文中的位置
+    let ac = AcBuilder::new().ascii_case_insensitive(true).normalize_width(true).build(&["hello"]);
+    assert_eq!(vec!["ＨＥＬＬＯ", "ｈｅｌｌｏ", "Hello"], find(&ac, "说ＨＥＬＬＯ和ｈｅｌｌｏ和Hello"));
+    assert_eq!("说hi吧", ac.replace_all("说ＨｅＬＬＯ吧", &["hi"]).unwrap());
+    let ac = AcBuilder::new().unicode_case_insensitive(true).build(&["straße", "ΣΟΦΙΑ"]);
+    assert_eq!(vec!["STRASSE", "Straße", "σοφια"], find(&ac, "STRASSE Straße σοφια"));
+    // 没有打开选项时区分大小写
+    assert!(AcManger::new(&["hello"]).find_iter("HELLO").next().is_none());
+}
+#[test]
+fn fold_leftmost_test() {
+    let ac = AcBuilder::new()
+        .match_kind(MatchKind::LeftmostLongest)
+        .unicode_case_insensitive(true)
+        .normalize_width(true)
+        .build(&["ss", "ssx", "ab"]);
+    let text = "ßx，ＡＢ";
+    let found: Vec<_> = ac.find_iter(text).map(|m| &text[m.start..m.end]).collect();
+    assert_eq!(vec!["ßx", "ＡＢ"], found);
+    let out = ac.redact(text.as_bytes(), Vec::new(), &["1", "2", "3"]).unwrap();
+    assert_eq!("2，3", String::from_utf8(out).unwrap());
+}
+#[test]
+fn serialize_test() {
+    let patterns = ["he", "she", "his", "hers", "中文", "ｈｉ"];
+    let text = "USHERS说中文，ＨＩＳ";
+    let ac = AcBuilder::new()
+        .match_kind(MatchKind::LeftmostLongest)
+        .ascii_case_insensitive(true)
+        .normalize_width(true)
+        .dfa(true)
+        .build(&patterns);
+    let expected: Vec<_> = ac.find_iter(text).collect();
+    assert_eq!(3, expected.len());
+    let bytes = ac.to_bytes();
+    let view = AcView::new(&bytes).unwrap();
+    assert_eq!(ac.len(), view.len());
+    assert_eq!(expected, view.find_iter(text).collect::<Vec<_>>());
+    // 查找的文本可以比保存的字节先释放
+    let owned = text.to_string();
+    assert_eq!(expected, view.find_iter(&owned).collect::<Vec<_>>());
+    drop(owned);
+    let loaded = AcManger::from_bytes(&bytes).unwrap();
+    assert!(loaded.dfa.is_some());
+    assert_eq!(expected, loaded.find_iter(text).collect::<Vec<_>>());
+    let mut newer = bytes.clone();
+    newer[4] = 9;
+    assert_eq!(SerialError::Version { expected: 1, found: 9 }, AcView::new(&newer).unwrap_err());
+    let mut corrupted = bytes.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 1;
+    assert_eq!(SerialError::Checksum, AcView::new(&corrupted).unwrap_err());
+    assert_eq!(SerialError::Truncated, AcView::new(&bytes[..bytes.len() - 4]).unwrap_err());
+}
+#[test]
+fn corrupted_root_test() {
+    let bytes = AcBuilder::new().build(&["he", "she"]).to_bytes();
+    let payload = serial::open(AC_MAGIC, &bytes).unwrap().to_vec();
+    // 根状态在20字节的头部之后，依次是转移的开始、个数、fail、output、word和depth
+    for (field, value) in [(2, 1), (3, 1), (4, 0)] {
+        let mut corrupted = payload.clone();
+        let offset = 20 + field * 4;
+        corrupted[offset..offset + 4].copy_from_slice(&u32::to_le_bytes(value));
+        let corrupted = serial::seal(AC_MAGIC, corrupted);
+        assert_eq!(SerialError::Corrupted("invalid automaton"), AcView::new(&corrupted).unwrap_err());
+    }
+    assert!(AcView::new(&serial::seal(AC_MAGIC, payload)).is_ok());
+}
+#[test]
+fn large_dictionary_test() {
+    let words: Vec<String> = (0..20000).map(|i| format!("w{}x", i)).collect();
+    let ac = AcBuilder::new().dfa(true).build(&words);
+    let found: Vec<_> = ac.find_iter("..w123x..w19999x").map(|m| m.pattern).collect();
+    assert_eq!(vec![123, 19999], found);
+}
+
+#[test]
+fn struct_sample_test() {
+    let root = AcNode::<()>::new();
     AcNode::push_str(&root, "her");
     AcNode::set_fail(&root);
     println!("{:#?}", root.borrow());
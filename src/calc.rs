@@ -1,73 +1,226 @@
-use std::ffi::NulError;
-
-use crate::token::{Expr, Num, TokensError};
-pub fn extra_num(expr: &Expr) -> Num {
+use crate::{
+    error::{LispError, LispResult},
+    token::{Expr, Num, TokensError},
+};
+pub fn extra_num(expr: &Expr, index: usize) -> LispResult<Num> {
     match expr {
-        Expr::Number(v) => *v,
-        // 如果相加的是一个表达式
-        Expr::Operator(op, exprs) => {
-            let r = Expr::switch(op, exprs);
-            match r {
-                Expr::Number(v) => v,
-                _ => panic!("{}", TokensError::Invalid),
-            }
-        }
-        _ => {
-            panic!("{}", TokensError::Invalid)
-        }
+        Expr::Number(v) => Ok(*v),
+        _ => Err(arg_type("number", expr, index)),
+    }
+}
+///第index个参数(从0开始)的类型错误
+pub fn arg_type(expected: &str, value: &Expr, index: usize) -> Box<LispError> {
+    TokensError::ArgType { expected, found: value.type_name(), index: index + 1 }.into()
+}
+///所有参数都必须是数字，出错时报告参数的位置
+pub fn nums(tokens: &[Expr]) -> LispResult<Vec<Num>> {
+    tokens
+        .iter()
+        .enumerate()
+        .map(|(i, token)| match token {
+            Expr::Number(v) => Ok(*v),
+            _ => Err(arg_type("number", token, i)),
+        })
+        .collect()
+}
+///检查参数个数
+pub fn args_count(tokens: &[Expr], expected: usize) -> LispResult<()> {
+    if tokens.len() != expected {
+        return Err(TokensError::ArgsCount { expected, found: tokens.len() }.into());
     }
+    Ok(())
 }
-pub fn calc<F: Fn(Num, Num) -> Num>(tokens: &[Expr], init: Num, op_func: F) -> Num {
+// 运算结果为None时表示溢出
+pub fn calc<F: Fn(Num, Num) -> Option<Num>>(tokens: &[Expr], init: Num, op_func: F) -> LispResult<Num> {
     let mut result = init;
-    for i in tokens.iter() {
-        let v = extra_num(i);
-        result = op_func(result, v)
+    for v in nums(tokens)? {
+        result = op_func(result, v).ok_or(TokensError::Overflow)?
+    }
+    Ok(result)
+}
+// 第一个参数作为初始值，其余参数依次计算
+pub fn fold<F: Fn(Num, Num) -> Option<Num>>(tokens: &[Expr], op_func: F) -> LispResult<Num> {
+    let nums = nums(tokens)?;
+    let (first, rest) = nums.split_first().ok_or(TokensError::ArgsCount { expected: 1, found: 0 })?;
+    let mut result = *first;
+    for v in rest {
+        result = op_func(result, *v).ok_or(TokensError::Overflow)?
     }
-    result
+    Ok(result)
 }
-pub fn head(tokens: &[Expr]) -> &Expr {
-    let expr = &tokens[0];
-    match &expr {
+// 比较相邻的两个数，全部满足时返回1，否则返回0
+pub fn compare<F: Fn(Num, Num) -> bool>(tokens: &[Expr], op_func: F) -> LispResult<Expr> {
+    let nums = nums(tokens)?;
+    let ok = nums.windows(2).all(|w| op_func(w[0], w[1]));
+    Ok(Expr::Number(ok as Num))
+}
+pub fn head(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    match &tokens[0] {
         // 获取内部的表达式，
-        Expr::QExpr(tokens) => &tokens[0],
-        _ => unreachable!(),
+        Expr::QExpr(tokens) => tokens
+            .first()
+            .cloned()
+            .ok_or(TokensError::InvalidExpression { expected: "non-empty qexpr", found: "{}" }.into()),
+        value => Err(arg_type("qexpr", value, 0)),
+    }
+}
+///去掉第一个元素，和原来的qexpr共享剩下的元素
+pub fn tail(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    match &tokens[0] {
+        Expr::QExpr(tokens) if !tokens.is_empty() => Ok(Expr::QExpr(tokens.tail())),
+        Expr::QExpr(_) => Err(TokensError::InvalidExpression { expected: "non-empty qexpr", found: "{}" }.into()),
+        value => Err(arg_type("qexpr", value, 0)),
+    }
+}
+///`cons 1 {2 3}`，在前面添加一个元素，不复制原来的元素
+pub fn cons(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 2)?;
+    match &tokens[1] {
+        Expr::QExpr(list) => Ok(Expr::QExpr(list.cons(tokens[0].to_owned()))),
+        value => Err(arg_type("qexpr", value, 1)),
     }
 }
-pub fn add(tokens: &[Expr]) -> Expr {
-    Expr::Number(calc(tokens, 0, |acc, x| acc + x))
+pub fn list(tokens: &[Expr]) -> LispResult<Expr> {
+    Ok(Expr::QExpr(tokens.to_vec().into()))
 }
-pub fn sub(tokens: &[Expr]) -> Expr {
-    Expr::Number(calc(tokens, 0, |acc, x| acc - x))
+pub fn add(tokens: &[Expr]) -> LispResult<Expr> {
+    Ok(Expr::Number(calc(tokens, 0, |acc, x| acc.checked_add(x))?))
 }
-pub fn mul(tokens: &[Expr]) -> Expr {
-    Expr::Number(calc(tokens, 1, |acc, x| acc * x))
+///`- 10 3 2`等于5，第一个参数依次减去其余参数
+pub fn sub(tokens: &[Expr]) -> LispResult<Expr> {
+    Ok(Expr::Number(fold(tokens, |acc, x| acc.checked_sub(x))?))
 }
-pub fn div(tokens: &[Expr]) -> Expr {
-    Expr::Number(calc(tokens, 1, |acc, x| acc / x))
+pub fn mul(tokens: &[Expr]) -> LispResult<Expr> {
+    Ok(Expr::Number(calc(tokens, 1, |acc, x| acc.checked_mul(x))?))
 }
-pub fn min(tokens: &[Expr]) -> Expr {
-    Expr::Number(calc(
+///`/ 20 2 5`等于2，第一个参数依次除以其余参数
+pub fn div(tokens: &[Expr]) -> LispResult<Expr> {
+    if tokens.iter().skip(1).any(|i| matches!(i, Expr::Number(0))) {
+        return Err(TokensError::DivideByZero.into());
+    }
+    Ok(Expr::Number(fold(tokens, |acc, x| acc.checked_div(x))?))
+}
+pub fn min(tokens: &[Expr]) -> LispResult<Expr> {
+    Ok(Expr::Number(calc(
         tokens,
         Num::MAX,
         |acc, x| {
-            if acc > x { x } else { acc }
+            if acc > x { Some(x) } else { Some(acc) }
         },
-    ))
+    )?))
 }
-pub fn max(tokens: &[Expr]) -> Expr {
-    Expr::Number(calc(
+pub fn max(tokens: &[Expr]) -> LispResult<Expr> {
+    Ok(Expr::Number(calc(
         tokens,
         Num::MIN,
         |acc, x| {
-            if acc < x { x } else { acc }
+            if acc < x { Some(x) } else { Some(acc) }
         },
-    ))
+    )?))
+}
+pub fn mmod(tokens: &[Expr]) -> LispResult<Expr> {
+    if tokens.iter().skip(1).any(|i| matches!(i, Expr::Number(0))) {
+        return Err(TokensError::DivideByZero.into());
+    }
+    Ok(Expr::Number(fold(tokens, |acc, x| acc.checked_rem(x))?))
+}
+pub fn mi(tokens: &[Expr]) -> LispResult<Expr> {
+    Ok(Expr::Number(fold(tokens, |acc, x| acc.checked_pow(x))?))
+}
+pub fn eq(tokens: &[Expr]) -> LispResult<Expr> {
+    compare(tokens, |a, b| a == b)
+}
+pub fn ne(tokens: &[Expr]) -> LispResult<Expr> {
+    compare(tokens, |a, b| a != b)
+}
+pub fn lt(tokens: &[Expr]) -> LispResult<Expr> {
+    compare(tokens, |a, b| a < b)
+}
+pub fn gt(tokens: &[Expr]) -> LispResult<Expr> {
+    compare(tokens, |a, b| a > b)
+}
+pub fn le(tokens: &[Expr]) -> LispResult<Expr> {
+    compare(tokens, |a, b| a <= b)
+}
+pub fn ge(tokens: &[Expr]) -> LispResult<Expr> {
+    compare(tokens, |a, b| a >= b)
+}
+///区间中的数字，开始大于结束时递减，不包含结束
+#[derive(Debug, Clone)]
+pub struct Range {
+    current: Option<Num>,
+    start: Num,
+    end: Num,
+    step: Num,
+}
+impl Range {
+    ///`end`、`start end`或者`start end step`
+    pub fn new(tokens: &[Expr]) -> LispResult<Range> {
+        let nums = nums(tokens)?;
+        let (start, end, step) = match nums.as_slice() {
+            [end] => (0, *end, 1),
+            [start, end] => (*start, *end, 1),
+            [start, end, step] => (*start, *end, *step),
+            _ => {
+                return Err(TokensError::InvalidExpression { expected: "range [start] end [step]", found: "args" }.into());
+            }
+        };
+        if step == 0 {
+            return Err(TokensError::InvalidExpression { expected: "step > 0", found: "0" }.into());
+        }
+        Ok(Range { current: Some(start), start, end, step })
+    }
+}
+impl Iterator for Range {
+    type Item = Num;
+    fn next(&mut self) -> Option<Num> {
+        let v = self.current?;
+        let in_range = if self.start <= self.end { v < self.end } else { v > self.end };
+        if !in_range {
+            return None;
+        }
+        self.current = if self.start <= self.end {
+            v.checked_add(self.step)
+        } else {
+            v.checked_sub(self.step)
+        };
+        Some(v)
+    }
+}
+///`range 5`返回`{0 1 2 3 4}`，也可以写成`range start end step`
+pub fn range(tokens: &[Expr]) -> LispResult<Expr> {
+    Ok(Expr::QExpr(Range::new(tokens)?.map(Expr::Number).collect()))
+}
+
+#[cfg(test)]
+fn run(s: &str) -> Expr {
+    crate::token::read(s).and_then(|expr| expr.eval(&crate::varmap::Env::new())).unwrap()
+}
+#[test]
+fn list_test() {
+    assert_eq!("{ 2 3 }", run("tail {1 2 3}").to_string());
+    assert_eq!("{ 0 1 2 }", run("cons 0 (list 1 2)").to_string());
+    assert!(matches!(run("head (tail (cons 0 {1 2}))"), Expr::Number(1)));
+}
+// `-`和`/`以第一个参数为初始值，其余参数依次计算
+#[test]
+fn sub_div_test() {
+    assert!(matches!(run("- 10 3 2"), Expr::Number(5)));
+    assert!(matches!(run("/ 20 2 5"), Expr::Number(2)));
+    assert!(matches!(run("- 7"), Expr::Number(7)));
 }
-pub fn mmod(tokens: &[Expr]) -> Expr {
-    Expr::Number(calc(&tokens[1..], extra_num(&tokens[0]), |acc, x| acc % x))
+#[test]
+fn range_test() {
+    assert_eq!("{ 0 1 2 3 4 }", run("range 5").to_string());
+    assert_eq!("{ 2 3 4 }", run("range 2 5").to_string());
+    assert_eq!("{ 0 3 6 9 }", run("range 0 10 3").to_string());
+    assert_eq!("{ 10 8 6 4 2 }", run("range 10 0 2").to_string());
+    assert_eq!("{ }", run("range 0").to_string());
 }
-pub fn mi(tokens: &[Expr]) -> Expr {
-    Expr::Number(calc(&tokens[1..], extra_num(&tokens[0]), |acc, x| {
-        acc.pow(x)
-    }))
+#[test]
+fn extra_num_test() {
+    let err = extra_num(&Expr::Char('a'), 1).unwrap_err().in_function("f");
+    assert_eq!("expected number, got char at argument 2 of f", err.message);
 }
@@ -0,0 +1,184 @@
+// 函数、生成器等没有结构的值按引用比较，其余的值按结构比较
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use crate::{
+    error::LispResult,
+    form::min_args,
+    map::entries,
+    token::{Expr, Num},
+};
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        compare(self, other) == Ordering::Equal
+    }
+}
+impl Eq for Expr {}
+impl PartialOrd for Expr {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+///不同类型按固定顺序排列，哈希表输出时按这个顺序排序
+impl Ord for Expr {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(self, other)
+    }
+}
+impl Hash for Expr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        rank(self).hash(state);
+        match self {
+            Expr::Number(v) => v.hash(state),
+            Expr::Symbol(v) => v.hash(state),
+            Expr::Str(v) => v.hash(state),
+            Expr::Char(v) => v.hash(state),
+            Expr::Record(record) => {
+                record.ty.id.hash(state);
+                record.values.hash(state);
+            }
+            Expr::QExpr(items) => items.iter().for_each(|i| i.hash(state)),
+            Expr::Vector(items) => items.iter().for_each(|i| i.hash(state)),
+            Expr::Operator(op, items, _) => {
+                op.hash(state);
+                items.hash(state);
+            }
+            // 哈希表的遍历顺序不固定，按键排序之后再计算
+            Expr::Map(map) => {
+                for (k, v) in entries(map) {
+                    k.hash(state);
+                    v.hash(state);
+                }
+            }
+            Expr::Error(err) => {
+                err.kind.hash(state);
+                err.message.hash(state);
+                err.value.hash(state);
+            }
+            _ => address(self).hash(state),
+        }
+    }
+}
+fn rank(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Number(_) => 0,
+        Expr::Symbol(_) => 1,
+        Expr::QExpr(_) => 2,
+        Expr::Operator(..) => 3,
+        Expr::Map(_) => 4,
+        Expr::Vector(_) => 5,
+        Expr::Str(_) => 6,
+        Expr::Char(_) => 7,
+        Expr::Record(_) => 8,
+        Expr::Error(_) => 9,
+        Expr::Function(_) => 10,
+        Expr::Native(_) => 11,
+        Expr::Macro(_) => 12,
+        Expr::Syntax(_) => 13,
+        Expr::Continuation(_) => 14,
+        Expr::Generator(_) => 15,
+    }
+}
+// 没有结构的值的地址，只用于比较和哈希
+fn address(expr: &Expr) -> usize {
+    match expr {
+        Expr::Function(v) | Expr::Macro(v) => Rc::as_ptr(v) as usize,
+        Expr::Native(v) => Rc::as_ptr(v) as usize,
+        Expr::Syntax(v) => Rc::as_ptr(v) as usize,
+        Expr::Continuation(v) => Rc::as_ptr(v) as usize,
+        Expr::Generator(v) => Rc::as_ptr(v) as usize,
+        _ => 0,
+    }
+}
+// 按结构比较，错误对象不比较位置
+fn compare(a: &Expr, b: &Expr) -> Ordering {
+    match (a, b) {
+        (Expr::Number(a), Expr::Number(b)) => a.cmp(b),
+        (Expr::Symbol(a), Expr::Symbol(b)) => a.cmp(b),
+        (Expr::Str(a), Expr::Str(b)) => a.cmp(b),
+        (Expr::Char(a), Expr::Char(b)) => a.cmp(b),
+        (Expr::Record(a), Expr::Record(b)) => {
+            (&a.ty.name, a.ty.id).cmp(&(&b.ty.name, b.ty.id)).then_with(|| a.values.cmp(&b.values))
+        }
+        (Expr::QExpr(a), Expr::QExpr(b)) => a.iter().cmp(b.iter()),
+        (Expr::Vector(a), Expr::Vector(b)) => a.iter().cmp(b.iter()),
+        (Expr::Operator(op_a, a, _), Expr::Operator(op_b, b, _)) => op_a.cmp(op_b).then_with(|| a.cmp(b)),
+        (Expr::Map(a), Expr::Map(b)) => entries(a).cmp(&entries(b)),
+        (Expr::Error(a), Expr::Error(b)) => (&a.kind, &a.message, &a.value).cmp(&(&b.kind, &b.message, &b.value)),
+        _ => rank(a).cmp(&rank(b)).then_with(|| address(a).cmp(&address(b))),
+    }
+}
+///`eq?`，数字、字符和符号按值比较，其余的值必须是同一个对象
+pub fn identical(a: &Expr, b: &Expr) -> bool {
+    match (a, b) {
+        (Expr::Number(_) | Expr::Char(_) | Expr::Symbol(_), _) => a == b,
+        (Expr::Str(a), Expr::Str(b)) => Rc::ptr_eq(a, b),
+        (Expr::QExpr(a), Expr::QExpr(b)) => a.ptr_eq(b),
+        (Expr::Vector(a), Expr::Vector(b)) => a.ptr_eq(b),
+        (Expr::Map(a), Expr::Map(b)) => a.ptr_eq(b),
+        (Expr::Record(a), Expr::Record(b)) => Rc::ptr_eq(a, b),
+        (Expr::Error(a), Expr::Error(b)) => Rc::ptr_eq(a, b),
+        // 代码没有共享的存储，按结构比较
+        (Expr::Operator(..), _) => a == b,
+        _ => rank(a) == rank(b) && address(a) == address(b),
+    }
+}
+///`equal? a b ...`，所有参数的结构都相同时返回1
+pub fn is_equal(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 2)?;
+    Ok(Expr::Number(tokens.windows(2).all(|w| w[0] == w[1]) as Num))
+}
+///`eq? a b ...`，所有参数都是同一个对象时返回1
+pub fn is_eq(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 2)?;
+    Ok(Expr::Number(tokens.windows(2).all(|w| identical(&w[0], &w[1])) as Num))
+}
+
+#[cfg(test)]
+fn run(s: &str, env: &crate::varmap::EnvRef) -> Expr {
+    crate::token::read(s).and_then(|expr| expr.eval(env)).unwrap()
+}
+#[test]
+fn equal_test() {
+    let env = crate::varmap::Env::new();
+    assert!(matches!(run("equal? {1 {2 \"a\"}} (list 1 {2 \"a\"})", &env), Expr::Number(1)));
+    assert!(matches!(run("equal? {1 {2 3}} {1 {2 4}}", &env), Expr::Number(0)));
+    assert!(matches!(run("equal? [1 {2}] [1 {2}] [1 {2}]", &env), Expr::Number(1)));
+    assert!(matches!(run("equal? #{a {1} b 2} #{b 2 a {1}}", &env), Expr::Number(1)));
+    assert!(matches!(run("equal? {1} [1]", &env), Expr::Number(0)));
+    // 函数按引用比较
+    assert!(matches!(run("equal? (\\ {x} {x}) (\\ {x} {x})", &env), Expr::Number(0)));
+    env.def("f", run("\\ {x} {x}", &env));
+    assert!(matches!(run("equal? f f", &env), Expr::Number(1)));
+    assert_eq!(run("{1 {2 3}}", &env), run("cons 1 {{2 3}}", &env));
+}
+#[test]
+fn identity_test() {
+    let env = crate::varmap::Env::new();
+    env.def("a", run("{1 2}", &env));
+    assert!(matches!(run("eq? a a", &env), Expr::Number(1)));
+    assert!(matches!(run("eq? a {1 2}", &env), Expr::Number(0)));
+    // tail共享原来的节点
+    assert!(matches!(run("eq? (tail a) (tail a)", &env), Expr::Number(1)));
+    assert!(matches!(run("eq? 1 1", &env), Expr::Number(1)));
+    assert!(matches!(run("eq? {} {}", &env), Expr::Number(1)));
+    assert!(matches!(run("eq? \"a\" \"a\"", &env), Expr::Number(0)));
+}
+// 哈希只依赖值的结构和函数的地址，和内部可变的部分无关
+#[allow(clippy::mutable_key_type)]
+#[test]
+fn key_test() {
+    let env = crate::varmap::Env::new();
+    env.def("f", run("\\ {x} {x}", &env));
+    env.def("m", run("hash-map f 1 [1 {2}] 2", &env));
+    assert!(matches!(run("get m f", &env), Expr::Number(1)));
+    assert!(matches!(run("get m [1 {2}]", &env), Expr::Number(2)));
+    assert!(matches!(run("contains? m (\\ {x} {x})", &env), Expr::Number(0)));
+    let mut set = std::collections::HashSet::new();
+    set.insert(run("{1 #{a [2]}}", &env));
+    assert!(set.contains(&run("list 1 #{a [2]}", &env)));
+}
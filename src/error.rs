@@ -0,0 +1,149 @@
+use std::{fmt, rc::Rc};
+
+use crate::{
+    calc::{arg_type, args_count},
+    persistent::List,
+    token::{Expr, Span, TokensError},
+};
+
+///运行时错误，可以被`try`捕获，也可以作为值传递
+#[derive(Debug, Clone)]
+pub struct LispError {
+    ///错误类型，例如`unbound-symbol`，用于匹配`catch`
+    pub kind: String,
+    pub message: String,
+    ///`throw`抛出的值
+    pub value: Expr,
+    ///出错的表达式在输入中的位置
+    pub span: Option<Span>,
+    ///参数类型错误的参数位置，从1开始，补充函数名之后清空
+    pub argument: Option<usize>,
+}
+// 错误比较大，装箱之后Result不会太大
+pub type LispResult<T> = Result<T, Box<LispError>>;
+
+impl LispError {
+    pub fn new<S: AsRef<str>>(kind: S, message: String) -> Self {
+        Self {
+            kind: kind.as_ref().to_string(),
+            message,
+            value: Expr::QExpr(List::new()),
+            span: None,
+            argument: None,
+        }
+    }
+    ///设置出错的位置，已经有位置时保留原来的位置
+    pub fn with_span(mut self: Box<Self>, span: Option<Span>) -> Box<Self> {
+        if self.span.is_none() {
+            self.span = span;
+        }
+        self
+    }
+    ///内置函数的参数类型错误补充函数名，例如`expected number, got qexpr at argument 2 of +`
+    pub fn in_function(mut self: Box<Self>, name: &str) -> Box<Self> {
+        // 已经有位置的错误是重新抛出的，信息已经完整
+        if self.span.is_none()
+            && let Some(index) = self.argument.take()
+        {
+            self.message = format!("{} at argument {} of {}", self.message, index, name);
+        }
+        self
+    }
+}
+impl fmt::Display for LispError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.message)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}", span)?;
+        }
+        Ok(())
+    }
+}
+impl From<TokensError<'_>> for Box<LispError> {
+    fn from(value: TokensError<'_>) -> Self {
+        let mut err = LispError::new(value.kind(), value.to_string());
+        if let TokensError::ArgType { index, .. } = value {
+            err.argument = Some(index);
+        }
+        Box::new(err)
+    }
+}
+
+fn extra_error(expr: &Expr, index: usize) -> LispResult<&LispError> {
+    match expr {
+        Expr::Error(err) => Ok(err),
+        _ => Err(arg_type("error", expr, index)),
+    }
+}
+///`throw 42`抛出任意值，抛出错误对象时保留原来的类型
+pub fn throw(tokens: &[Expr]) -> LispResult<Expr> {
+    match tokens {
+        [Expr::Error(err)] => Err(Box::new(err.as_ref().to_owned())),
+        [value] => {
+            let mut err = LispError::new("user", value.to_string());
+            err.value = value.to_owned();
+            Err(Box::new(err))
+        }
+        _ => Err(TokensError::ArgsCount { expected: 1, found: tokens.len() }.into()),
+    }
+}
+///创建错误对象，`error not-found 42`，类型是不计算的符号
+pub fn make_error(kind: &str, tokens: &[Expr]) -> Expr {
+    let value = match tokens {
+        [value] => value.to_owned(),
+        _ => Expr::QExpr(tokens.to_vec().into()),
+    };
+    let message = tokens.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(" ");
+    let mut err = LispError::new(kind, message);
+    err.value = value;
+    Expr::Error(Rc::new(err))
+}
+pub fn error_kind(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::Symbol(extra_error(&tokens[0], 0)?.kind.to_owned()))
+}
+pub fn error_message(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::Str(extra_error(&tokens[0], 0)?.message.as_str().into()))
+}
+pub fn error_value(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(extra_error(&tokens[0], 0)?.value.to_owned())
+}
+///错误的位置`{start end}`，没有位置时返回`{}`
+pub fn error_span(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    let span = extra_error(&tokens[0], 0)?.span;
+    Ok(Expr::QExpr(
+        span.map(|i| vec![Expr::Number(i.start as _), Expr::Number(i.end as _)])
+            .unwrap_or_default()
+            .into(),
+    ))
+}
+
+#[cfg(test)]
+fn run(s: &str) -> LispResult<Expr> {
+    crate::token::read(s).and_then(|expr| expr.eval(&crate::varmap::Env::new()))
+}
+#[test]
+fn accessor_test() {
+    for name in ["error-kind", "error-message", "error-value", "error-span"] {
+        assert_eq!("arity-error", run(name).unwrap_err().kind);
+        assert_eq!("arity-error", run(&format!("{} (error e 1) 2", name)).unwrap_err().kind);
+    }
+    assert_eq!("\"1 2\"", run("error-message (error not-found 1 2)").unwrap().to_string());
+    assert_eq!("not-found", run("error-kind (error not-found 1)").unwrap().to_string());
+    assert_eq!("{ 1 2 }", run("error-value (error not-found 1 2)").unwrap().to_string());
+    let err = run("error-kind 1").unwrap_err();
+    assert_eq!("expected error, got number at argument 1 of error-kind", err.message);
+}
+#[test]
+fn in_function_test() {
+    let err: Box<LispError> = TokensError::ArgType { expected: "number", found: "qexpr", index: 2 }.into();
+    assert_eq!(Some(2), err.argument);
+    let err = err.in_function("+").in_function("map");
+    assert_eq!("expected number, got qexpr at argument 2 of +", err.message);
+    // 其他错误的信息不变，即使以数字结尾
+    let err: Box<LispError> = TokensError::IndexOutOfBounds { index: 3, len: 1 }.into();
+    assert_eq!("Index 3 out of bounds for length 1", err.in_function("nth").message);
+}
@@ -0,0 +1,876 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    calc::{Range, arg_type, args_count, extra_num},
+    error::{LispError, LispResult, make_error},
+    form::{bindings, lambda, min_args, symbol},
+    macros::{defmacro, expand_call, macroexpand, quasiquote},
+    pattern::{Clause, clauses, match_pattern},
+    syntax::defsyntax,
+    persistent::{List, TrieVector},
+    record::defrecord,
+    token::{Builtin, Expr, Function, Native, Span, TokensError},
+    varmap::{Env, EnvRef},
+};
+
+///call/cc捕获的continuation，保存调用call/cc时还没有完成的计算
+#[derive(Clone)]
+pub struct Continuation {
+    stack: Vec<(Frame, Option<Span>)>,
+}
+// 栈中保存了作用域，打印作用域会无限递归
+impl fmt::Debug for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Continuation({} frames)", self.stack.len())
+    }
+}
+
+///生成器，每次恢复执行到下一个`yield`为止
+pub struct Generator {
+    state: RefCell<GenState>,
+    // `done?`提前取出的值
+    peeked: RefCell<Option<Expr>>,
+}
+enum GenState {
+    Start { body: Expr, env: EnvRef },
+    // 暂停时保存从生成器开始到`yield`之间的栈
+    Suspended(Vec<(Frame, Option<Span>)>),
+    Running,
+    Done,
+}
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match &*self.state.borrow() {
+            GenState::Start { .. } => "start",
+            GenState::Suspended(_) => "suspended",
+            GenState::Running => "running",
+            GenState::Done => "done",
+        };
+        write!(f, "Generator({})", state)
+    }
+}
+impl Generator {
+    fn is_done(&self) -> bool {
+        matches!(*self.state.borrow(), GenState::Done)
+    }
+}
+
+// 参数计算完成之后调用的对象
+#[derive(Debug, Clone)]
+enum Callee {
+    // 保存函数名，用于补充类型错误的信息
+    Builtin(String, Builtin),
+    Native(Rc<Native>),
+    Function(Rc<Function>),
+    Continuation(Rc<Continuation>),
+    CallCc,
+    // 创建错误对象，保存错误类型
+    Error(String),
+    Yield,
+    Next,
+    Done,
+    // 取出前n个元素，collect取出全部元素
+    Take,
+    Collect,
+}
+// for循环剩下的元素
+#[derive(Debug, Clone)]
+enum ForItems {
+    List(List),
+    Vector(TrieVector, usize),
+    // 按字符遍历字符串，保存下一个字符的字节位置
+    Chars(Rc<str>, usize),
+    Range(Range),
+}
+impl ForItems {
+    fn next(&mut self) -> Option<Expr> {
+        match self {
+            ForItems::List(items) => {
+                let item = items.first()?.to_owned();
+                *items = items.tail();
+                Some(item)
+            }
+            ForItems::Vector(items, index) => {
+                let item = items.get(*index)?.to_owned();
+                *index += 1;
+                Some(item)
+            }
+            ForItems::Chars(s, index) => {
+                let c = s[*index..].chars().next()?;
+                *index += c.len_utf8();
+                Some(Expr::Char(c))
+            }
+            ForItems::Range(range) => range.next().map(Expr::Number),
+        }
+    }
+}
+// `(catch kind {e} body...)`，kind为`_`时捕获所有错误
+#[derive(Debug, Clone)]
+struct Handler {
+    kind: String,
+    var: String,
+    body: Expr,
+}
+// 等待子表达式结果的计算，栈中的帧就是continuation
+#[derive(Debug, Clone)]
+enum Frame {
+    // 依次计算参数
+    Args { callee: Callee, args: Vec<Expr>, values: Vec<Expr>, env: EnvRef },
+    If { then: Expr, otherwise: Expr, env: EnvRef },
+    Do { exprs: Vec<Expr>, index: usize, env: EnvRef },
+    // let、let*和letrec的绑定，值在eval_env中计算，绑定到local中
+    Bind { names: Vec<String>, exprs: Vec<Expr>, index: usize, eval_env: EnvRef, local: EnvRef, body: Expr },
+    Set { name: String, env: EnvRef },
+    WhileCond { cond: Expr, body: Expr, result: Expr, env: EnvRef },
+    WhileBody { cond: Expr, body: Expr, env: EnvRef },
+    ForStart { name: String, range: bool, body: Expr, env: EnvRef },
+    ForBody { name: String, items: ForItems, body: Expr, env: EnvRef },
+    // 出错时从栈中找到最近的Try帧，id用来判断调用continuation时是否离开了这个try
+    Try { id: usize, handlers: Vec<Handler>, finally: Option<Expr>, env: EnvRef },
+    // finally执行完之后返回原来的值或者继续抛出错误
+    Finally { result: LispResult<Expr> },
+    // 生成器的边界，`yield`保存这一帧上面的栈，stop为true时结束后抛出stop-iteration
+    Generator { generator: Rc<Generator>, stop: bool },
+    // 从生成器中取值的调用者，生成器结束时返回{}，通过生成器的状态判断是否结束
+    Peek { generator: Rc<Generator> },
+    Take { generator: Rc<Generator>, n: usize, items: Vec<Expr> },
+    ForGen { name: String, generator: Rc<Generator>, body: Expr, result: Expr, env: EnvRef },
+    ForGenBody { name: String, generator: Rc<Generator>, body: Expr, env: EnvRef },
+    // 计算完要匹配的值之后依次尝试每个分支
+    Match { clauses: Vec<Clause>, env: EnvRef },
+    // 条件不成立时从index开始继续匹配
+    MatchGuard { value: Expr, clauses: Vec<Clause>, index: usize, body: Expr, env: EnvRef, local: EnvRef },
+}
+// 每一步要么计算一个表达式，要么把值返回给栈顶的帧
+enum Step {
+    Eval(Expr, EnvRef),
+    Return(Expr),
+}
+// 栈中的每一帧都记录了创建它的表达式的位置，出错时使用
+struct Machine {
+    stack: Vec<(Frame, Option<Span>)>,
+    span: Option<Span>,
+}
+
+///计算表达式，使用显式的栈代替递归，递归深度不会增加rust的调用栈
+pub fn eval(expr: &Expr, env: &EnvRef) -> LispResult<Expr> {
+    let mut machine = Machine { stack: Vec::new(), span: None };
+    let mut step = Step::Eval(expr.to_owned(), env.clone());
+    loop {
+        let next = match step {
+            Step::Eval(expr, env) => machine.eval_expr(expr, env),
+            Step::Return(value) => match machine.stack.pop() {
+                Some((frame, span)) => {
+                    machine.span = span;
+                    machine.resume(frame, value)
+                }
+                None => return Ok(value),
+            },
+        };
+        step = match next {
+            Ok(step) => step,
+            Err(err) => machine.unwind(err)?,
+        };
+    }
+}
+// 把多个表达式合成一个`do`表达式
+fn sequence(mut exprs: Vec<Expr>) -> Expr {
+    if exprs.len() == 1 {
+        return exprs.remove(0);
+    }
+    Expr::Operator("do".to_string(), exprs, None)
+}
+// `(catch kind {e} body...)`
+fn handler(clause: &[Expr]) -> LispResult<Handler> {
+    min_args(clause, 2)?;
+    let var = match &clause[1] {
+        Expr::QExpr(var) if var.len() == 1 => symbol(var.first().unwrap_or(&clause[1]))?,
+        var => symbol(var)?,
+    };
+    let body = clause[2..].iter().map(|i| i.to_code()).collect::<LispResult<_>>()?;
+    Ok(Handler {
+        kind: symbol(&clause[0])?.to_owned(),
+        var: var.to_owned(),
+        body: sequence(body),
+    })
+}
+fn callee(value: Expr) -> LispResult<Callee> {
+    match value {
+        Expr::Function(func) => Ok(Callee::Function(func)),
+        Expr::Native(native) => Ok(Callee::Native(native)),
+        Expr::Continuation(k) => Ok(Callee::Continuation(k)),
+        value => Err(TokensError::Type { expected: "function", found: value.type_name() }.into()),
+    }
+}
+fn try_id(frame: &Frame) -> Option<usize> {
+    match frame {
+        Frame::Try { id, .. } => Some(*id),
+        _ => None,
+    }
+}
+fn extra_gen(expr: &Expr, index: usize) -> LispResult<Rc<Generator>> {
+    match expr {
+        Expr::Generator(generator) => Ok(generator.clone()),
+        _ => Err(arg_type("generator", expr, index)),
+    }
+}
+// `if`、`while`和`when`的条件，条件不是函数的参数，错误中没有参数的位置
+fn condition(value: &Expr) -> LispResult<bool> {
+    match value {
+        Expr::Number(n) => Ok(*n != 0),
+        _ => Err(TokensError::Type { expected: "number", found: value.type_name() }.into()),
+    }
+}
+
+impl Machine {
+    fn push(&mut self, frame: Frame) {
+        self.stack.push((frame, self.span));
+    }
+    fn eval_expr(&mut self, expr: Expr, env: EnvRef) -> LispResult<Step> {
+        match expr {
+            Expr::Symbol(name) => match env.get(&name) {
+                Some(value) => Ok(Step::Return(value)),
+                None => Err(TokensError::Unbound(&name).into()),
+            },
+            Expr::Operator(op, args, span) => {
+                // 宏展开生成的表达式没有位置，使用外层表达式的位置
+                if span.is_some() {
+                    self.span = span;
+                }
+                self.form(op, args, env)
+            }
+            value => Ok(Step::Return(value)),
+        }
+    }
+    // 出错时沿着栈向下查找能处理这个错误的try，没有找到时返回错误
+    fn unwind(&mut self, err: Box<LispError>) -> LispResult<Step> {
+        let err = err.with_span(self.span);
+        while let Some((frame, span)) = self.stack.pop() {
+            let Frame::Try { id, handlers, finally, env } = frame else {
+                // 生成器中的错误没有被处理时，生成器结束
+                if let Frame::Generator { generator, .. } = frame {
+                    generator.state.replace(GenState::Done);
+                }
+                continue;
+            };
+            self.span = span;
+            if let Some(handler) = handlers.iter().find(|i| i.kind == "_" || i.kind == err.kind) {
+                let local = Env::child(&env);
+                local.def(&handler.var, Expr::Error(Rc::new(*err)));
+                // 处理错误的代码执行完或者再次出错时，还需要执行finally
+                self.push(Frame::Try { id, handlers: vec![], finally, env });
+                return Ok(Step::Eval(handler.body.to_owned(), local));
+            }
+            if let Some(finally) = finally {
+                self.push(Frame::Finally { result: Err(err) });
+                return Ok(Step::Eval(finally, env));
+            }
+        }
+        Err(err)
+    }
+    // 特殊形式的参数不会提前计算，其他的调用先计算参数
+    fn form(&mut self, op: String, mut args: Vec<Expr>, env: EnvRef) -> LispResult<Step> {
+        match op.as_str() {
+            // `if (== x 0) {1} {2}`，非0为真，只计算选中的分支
+            "if" => {
+                min_args(&args, 2)?;
+                let otherwise = match args.get(2) {
+                    Some(otherwise) => otherwise.to_code()?,
+                    None => Expr::QExpr(List::new()),
+                };
+                self.push(Frame::If { then: args[1].to_code()?, otherwise, env: env.clone() });
+                Ok(Step::Eval(args[0].to_code()?, env))
+            }
+            "do" | "begin" => self.do_next(args, 0, env),
+            // `let {{x 1} {y 2}} (+ x y)`，所有值在外层作用域中计算，变量只在子作用域中可见
+            // `let*`按顺序绑定，后面的值可以使用前面的变量
+            // `letrec`的值都在子作用域中计算，可以定义相互递归的局部函数。
+            // 子作用域中的函数引用子作用域本身，形成Rc的循环，子作用域不会被释放，
+            // 函数可能在返回值中被继续使用，不能改成弱引用
+            "let" | "let*" | "letrec" => {
+                min_args(&args, 1)?;
+                let local = Env::child(&env);
+                let (names, exprs): (Vec<String>, Vec<Expr>) = bindings(&args[0])?
+                    .into_iter()
+                    .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                    .unzip();
+                let eval_env = if op == "let" { env } else { local.clone() };
+                if op == "letrec" {
+                    // 先占位，保证函数体中引用的变量都在子作用域中
+                    for name in names.iter() {
+                        local.def(name, Expr::QExpr(List::new()));
+                    }
+                }
+                let body = sequence(args.split_off(1));
+                self.bind_next(names, exprs, 0, eval_env, local, body)
+            }
+            "\\" => Ok(Step::Return(lambda(&args, &env)?)),
+            // `set x (+ x 1)`修改已经存在的变量
+            "set" => {
+                args_count(&args, 2)?;
+                let name = symbol(&args[0])?.to_owned();
+                self.push(Frame::Set { name, env: env.clone() });
+                Ok(Step::Eval(args[1].to_owned(), env))
+            }
+            // `while (< i 10) (set i (+ i 1))`，条件非0时重复计算循环体，返回最后一次循环体的值
+            "while" => {
+                min_args(&args, 1)?;
+                let cond = args[0].to_code()?;
+                let body = sequence(args[1..].iter().map(|i| i.to_code()).collect::<LispResult<_>>()?);
+                self.push(Frame::WhileCond { cond: cond.clone(), body, result: Expr::QExpr(List::new()), env: env.clone() });
+                Ok(Step::Eval(cond, env))
+            }
+            // `for x {1 2 3} (+ x 1)`，依次把qexpr中的元素绑定到变量上。
+            // 只有直接写在`for`中的`(range ...)`不会生成整个列表，`range`的结果保存到变量之后已经是列表
+            "for" => {
+                min_args(&args, 2)?;
+                let name = symbol(&args[0])?.to_owned();
+                let body = sequence(args[2..].iter().map(|i| i.to_code()).collect::<LispResult<_>>()?);
+                let (range, seq) = match &args[1] {
+                    Expr::Operator(op, range_args, span) if op == "range" => {
+                        (true, Expr::Operator("list".to_string(), range_args.to_owned(), *span))
+                    }
+                    seq => (false, seq.to_owned()),
+                };
+                self.push(Frame::ForStart { name, range, body, env: env.clone() });
+                Ok(Step::Eval(seq, env))
+            }
+            // `try body... (catch kind {e} handler...) (finally cleanup...)`
+            "try" => {
+                let mut body = Vec::new();
+                let mut handlers = Vec::new();
+                let mut finally = None;
+                for arg in args {
+                    match arg {
+                        Expr::Operator(op, clause, _) if op == "catch" => handlers.push(handler(&clause)?),
+                        Expr::Operator(op, clause, _) if op == "finally" => {
+                            let clause = clause.iter().map(|i| i.to_code()).collect::<LispResult<_>>()?;
+                            finally = Some(sequence(clause));
+                        }
+                        _ => body.push(arg),
+                    }
+                }
+                self.push(Frame::Try { id: env.next_id(), handlers, finally, env: env.clone() });
+                self.do_next(body, 0, env)
+            }
+            // `error not-found 42`创建错误对象，类型不会被计算
+            "error" => {
+                min_args(&args, 1)?;
+                let kind = symbol(&args[0])?.to_owned();
+                self.args_next(Callee::Error(kind), args.split_off(1), vec![], env)
+            }
+            // `generator (yield 1) (yield 2)`，创建生成器，第一次取值时才开始执行
+            "generator" => {
+                let body = sequence(args.iter().map(|i| i.to_code()).collect::<LispResult<_>>()?);
+                Ok(Step::Return(Expr::Generator(Rc::new(Generator {
+                    state: RefCell::new(GenState::Start { body, env }),
+                    peeked: RefCell::new(None),
+                }))))
+            }
+            "yield" => self.args_next(Callee::Yield, args, vec![], env),
+            "next" => self.args_next(Callee::Next, args, vec![], env),
+            "done?" => self.args_next(Callee::Done, args, vec![], env),
+            "take" => self.args_next(Callee::Take, args, vec![], env),
+            "collect" => self.args_next(Callee::Collect, args, vec![], env),
+            // `match v {{x & xs} x} {n when (> n 0) n} {_ 0}`，依次匹配每个分支
+            "match" => {
+                min_args(&args, 1)?;
+                let clauses = clauses(&args[1..])?;
+                self.push(Frame::Match { clauses, env: env.clone() });
+                Ok(Step::Eval(args[0].to_owned(), env))
+            }
+            "defrecord" => Ok(Step::Return(defrecord(&args, &env)?)),
+            "defmacro" => Ok(Step::Return(defmacro(&args, &env)?)),
+            "defsyntax" => Ok(Step::Return(defsyntax(&args, &env)?)),
+            "macroexpand" => Ok(Step::Return(macroexpand(&args, &env)?)),
+            "quasiquote" => Ok(Step::Return(quasiquote(&args, &env)?)),
+            "call/cc" => {
+                args_count(&args, 1)?;
+                self.args_next(Callee::CallCc, args, vec![], env)
+            }
+            _ => match Expr::builtin(&op) {
+                Some(func) => self.args_next(Callee::Builtin(op, func), args, vec![], env),
+                // 不是内置函数时查找作用域中的变量
+                None => match env.get(&op) {
+                    Some(Expr::Function(func)) => self.args_next(Callee::Function(func), args, vec![], env),
+                    Some(Expr::Native(native)) => self.args_next(Callee::Native(native), args, vec![], env),
+                    Some(Expr::Continuation(k)) => self.args_next(Callee::Continuation(k), args, vec![], env),
+                    // 没有在展开阶段展开的宏，例如函数体中的宏，在计算时展开
+                    Some(Expr::Macro(func)) => Ok(Step::Eval(expand_call(&func, &args)?, env)),
+                    Some(Expr::Syntax(rules)) => Ok(Step::Eval(rules.expand(&op, &args, &env)?, env)),
+                    Some(value) if args.is_empty() => Ok(Step::Return(value)),
+                    Some(_) => Err(TokensError::InvalidExpression { expected: "function", found: &op }.into()),
+                    None => Err(TokensError::Unbound(&op).into()),
+                },
+            },
+        }
+    }
+    fn do_next(&mut self, exprs: Vec<Expr>, index: usize, env: EnvRef) -> LispResult<Step> {
+        match exprs.len() {
+            0 => Ok(Step::Return(Expr::QExpr(List::new()))),
+            // 最后一个表达式不需要保存帧，尾调用不会让栈变长
+            len if index + 1 >= len => Ok(Step::Eval(exprs[index].to_owned(), env)),
+            _ => {
+                let expr = exprs[index].to_owned();
+                self.push(Frame::Do { exprs, index: index + 1, env: env.clone() });
+                Ok(Step::Eval(expr, env))
+            }
+        }
+    }
+    fn bind_next(
+        &mut self,
+        names: Vec<String>,
+        exprs: Vec<Expr>,
+        index: usize,
+        eval_env: EnvRef,
+        local: EnvRef,
+        body: Expr,
+    ) -> LispResult<Step> {
+        if index >= names.len() {
+            return Ok(Step::Eval(body, local));
+        }
+        let expr = exprs[index].to_owned();
+        let env = eval_env.clone();
+        self.push(Frame::Bind { names, exprs, index, eval_env, local, body });
+        Ok(Step::Eval(expr, env))
+    }
+    fn args_next(&mut self, callee: Callee, args: Vec<Expr>, values: Vec<Expr>, env: EnvRef) -> LispResult<Step> {
+        if values.len() >= args.len() {
+            return self.call(callee, values);
+        }
+        let arg = args[values.len()].to_owned();
+        self.push(Frame::Args { callee, args, values, env: env.clone() });
+        Ok(Step::Eval(arg, env))
+    }
+    fn for_next(&mut self, name: String, mut items: ForItems, body: Expr, result: Expr, env: EnvRef) -> LispResult<Step> {
+        match items.next() {
+            Some(item) => {
+                // 每次循环都使用新的作用域，循环变量不会泄漏到外面
+                let local = Env::child(&env);
+                local.def(&name, item);
+                self.push(Frame::ForBody { name, items, body: body.clone(), env });
+                Ok(Step::Eval(body, local))
+            }
+            None => Ok(Step::Return(result)),
+        }
+    }
+    fn call(&mut self, callee_value: Callee, values: Vec<Expr>) -> LispResult<Step> {
+        match callee_value {
+            Callee::Builtin(name, func) => Ok(Step::Return(func(&values).map_err(|err| err.in_function(&name))?)),
+            Callee::Native(native) => {
+                Ok(Step::Return((native.func)(&values).map_err(|err| err.in_function(&native.name))?))
+            }
+            Callee::Function(func) => {
+                let local = Expr::bind(&func, values)?;
+                Ok(Step::Eval(func.body.to_owned(), local))
+            }
+            // 回到捕获时的栈，离开的try中的finally从内到外依次执行完之后再返回
+            Callee::Continuation(k) => {
+                let kept: Vec<usize> = k.stack.iter().filter_map(|(frame, _)| try_id(frame)).collect();
+                let pending: Vec<_> = std::mem::take(&mut self.stack)
+                    .into_iter()
+                    .filter(|(frame, _)| matches!(frame, Frame::Try { id, finally: Some(_), .. } if !kept.contains(id)))
+                    .collect();
+                self.stack = k.stack.to_owned();
+                for (frame, span) in pending {
+                    if let Frame::Try { id, finally, env, .. } = frame {
+                        self.stack.push((Frame::Try { id, handlers: vec![], finally, env }, span));
+                    }
+                }
+                Ok(Step::Return(values.into_iter().next().unwrap_or(Expr::QExpr(List::new()))))
+            }
+            // `call/cc (\ {k} {...})`，把当前的栈作为参数传给函数
+            Callee::CallCc => {
+                let func = values.into_iter().next().unwrap_or(Expr::QExpr(List::new()));
+                let k = Expr::Continuation(Rc::new(Continuation { stack: self.stack.to_owned() }));
+                self.call(callee(func)?, vec![k])
+            }
+            Callee::Error(kind) => Ok(Step::Return(make_error(&kind, &values))),
+            // 把生成器边界上面的栈保存到生成器中，值返回给取值的调用者
+            Callee::Yield => {
+                let value = values.into_iter().next().unwrap_or(Expr::QExpr(List::new()));
+                let index = self
+                    .stack
+                    .iter()
+                    .rposition(|(frame, _)| matches!(frame, Frame::Generator { .. }))
+                    .ok_or(TokensError::InvalidExpression { expected: "yield inside generator", found: "yield" })?;
+                let saved = self.stack.split_off(index + 1);
+                if let Some((Frame::Generator { generator, .. }, span)) = self.stack.pop() {
+                    generator.state.replace(GenState::Suspended(saved));
+                    self.span = span;
+                }
+                Ok(Step::Return(value))
+            }
+            // `next generator`取出下一个值，`next generator v`时`yield`的返回值是v
+            Callee::Next => {
+                min_args(&values, 1)?;
+                let send = values.get(1).cloned().unwrap_or(Expr::QExpr(List::new()));
+                self.advance(extra_gen(&values[0], 0).map_err(|err| err.in_function("next"))?, send, true)
+            }
+            Callee::Done => {
+                args_count(&values, 1)?;
+                let generator = extra_gen(&values[0], 0).map_err(|err| err.in_function("done?"))?;
+                if generator.peeked.borrow().is_some() {
+                    return Ok(Step::Return(Expr::Number(0)));
+                }
+                self.push(Frame::Peek { generator: generator.clone() });
+                self.advance(generator, Expr::QExpr(List::new()), false)
+            }
+            // `take 5 generator`，qexpr、向量和生成器都可以取前n个元素
+            Callee::Take => {
+                args_count(&values, 2)?;
+                let n = extra_num(&values[0], 0).map_err(|err| err.in_function("take"))? as usize;
+                match &values[1] {
+                    Expr::QExpr(items) => Ok(Step::Return(Expr::QExpr(items.iter().take(n).cloned().collect()))),
+                    Expr::Vector(items) => {
+                        Ok(Step::Return(Expr::Vector(items.slice(0, n.min(items.len())).unwrap_or_default())))
+                    }
+                    seq => self.take_next(extra_gen(seq, 1).map_err(|err| err.in_function("take"))?, n, vec![]),
+                }
+            }
+            Callee::Collect => {
+                args_count(&values, 1)?;
+                let generator = extra_gen(&values[0], 0).map_err(|err| err.in_function("collect"))?;
+                self.take_next(generator, usize::MAX, vec![])
+            }
+        }
+    }
+    // 恢复生成器的执行
+    fn advance(&mut self, generator: Rc<Generator>, send: Expr, stop: bool) -> LispResult<Step> {
+        if let Some(value) = generator.peeked.borrow_mut().take() {
+            return Ok(Step::Return(value));
+        }
+        match generator.state.replace(GenState::Running) {
+            GenState::Start { body, env } => {
+                self.push(Frame::Generator { generator, stop });
+                Ok(Step::Eval(body, env))
+            }
+            GenState::Suspended(saved) => {
+                self.push(Frame::Generator { generator, stop });
+                self.stack.extend(saved);
+                Ok(Step::Return(send))
+            }
+            GenState::Running => {
+                Err(TokensError::InvalidExpression { expected: "suspended generator", found: "running generator" }.into())
+            }
+            GenState::Done => {
+                generator.state.replace(GenState::Done);
+                if stop {
+                    return Err(TokensError::StopIteration.into());
+                }
+                Ok(Step::Return(Expr::QExpr(List::new())))
+            }
+        }
+    }
+    fn match_next(&mut self, value: Expr, clauses: Vec<Clause>, index: usize, env: EnvRef) -> LispResult<Step> {
+        for (i, clause) in clauses.iter().enumerate().skip(index) {
+            let mut bindings = Vec::new();
+            if !match_pattern(&clause.pattern, &value, &env, &mut bindings) {
+                continue;
+            }
+            let local = Env::child(&env);
+            for (name, v) in bindings {
+                local.def(&name, v);
+            }
+            let body = clause.body.clone();
+            let Some(guard) = clause.guard.clone() else {
+                return Ok(Step::Eval(body, local));
+            };
+            self.push(Frame::MatchGuard { value, clauses, index: i + 1, body, env, local: local.clone() });
+            return Ok(Step::Eval(guard, local));
+        }
+        let mut err = LispError::new(TokensError::NonExhaustive.kind(), format!("No pattern matched {}", value));
+        err.value = value;
+        Err(Box::new(err))
+    }
+    fn take_next(&mut self, generator: Rc<Generator>, n: usize, items: Vec<Expr>) -> LispResult<Step> {
+        if items.len() >= n {
+            return Ok(Step::Return(Expr::QExpr(items.into())));
+        }
+        self.push(Frame::Take { generator: generator.clone(), n, items });
+        self.advance(generator, Expr::QExpr(List::new()), false)
+    }
+    fn for_gen_next(&mut self, name: String, generator: Rc<Generator>, body: Expr, result: Expr, env: EnvRef) -> LispResult<Step> {
+        self.push(Frame::ForGen { name, generator: generator.clone(), body, result, env });
+        self.advance(generator, Expr::QExpr(List::new()), false)
+    }
+    // 子表达式计算完成，继续栈顶帧的计算
+    fn resume(&mut self, frame: Frame, value: Expr) -> LispResult<Step> {
+        match frame {
+            Frame::Args { callee, args, mut values, env } => {
+                values.push(value);
+                self.args_next(callee, args, values, env)
+            }
+            Frame::If { then, otherwise, env } => {
+                if condition(&value)? {
+                    Ok(Step::Eval(then, env))
+                } else {
+                    Ok(Step::Eval(otherwise, env))
+                }
+            }
+            Frame::Do { exprs, index, env } => self.do_next(exprs, index, env),
+            Frame::Bind { names, exprs, index, eval_env, local, body } => {
+                local.def(&names[index], value);
+                self.bind_next(names, exprs, index + 1, eval_env, local, body)
+            }
+            Frame::Set { name, env } => {
+                if !env.set(&name, value.clone()) {
+                    return Err(TokensError::Unbound(&name).into());
+                }
+                Ok(Step::Return(value))
+            }
+            Frame::WhileCond { cond, body, result, env } => {
+                if !condition(&value)? {
+                    return Ok(Step::Return(result));
+                }
+                self.push(Frame::WhileBody { cond, body: body.clone(), env: env.clone() });
+                Ok(Step::Eval(body, env))
+            }
+            Frame::WhileBody { cond, body, env } => {
+                self.push(Frame::WhileCond { cond: cond.clone(), body, result: value, env: env.clone() });
+                Ok(Step::Eval(cond, env))
+            }
+            Frame::ForStart { name, range, body, env } => {
+                let items = match value {
+                    Expr::QExpr(items) if range => ForItems::Range(Range::new(&items.to_vec())?),
+                    Expr::QExpr(items) => ForItems::List(items),
+                    Expr::Vector(items) => ForItems::Vector(items, 0),
+                    Expr::Str(s) => ForItems::Chars(s, 0),
+                    Expr::Generator(generator) => return self.for_gen_next(name, generator, body, Expr::QExpr(List::new()), env),
+                    _ => return Err(TokensError::InvalidExpression { expected: "qexpr", found: "expr" }.into()),
+                };
+                self.for_next(name, items, body, Expr::QExpr(List::new()), env)
+            }
+            Frame::ForBody { name, items, body, env } => self.for_next(name, items, body, value, env),
+            // try中的代码正常结束
+            Frame::Try { finally, env, .. } => match finally {
+                Some(finally) => {
+                    self.push(Frame::Finally { result: Ok(value) });
+                    Ok(Step::Eval(finally, env))
+                }
+                None => Ok(Step::Return(value)),
+            },
+            Frame::Finally { result } => Ok(Step::Return(result?)),
+            // 生成器中的代码执行完
+            Frame::Generator { generator, stop } => {
+                generator.state.replace(GenState::Done);
+                if stop {
+                    return Err(TokensError::StopIteration.into());
+                }
+                Ok(Step::Return(Expr::QExpr(List::new())))
+            }
+            Frame::Peek { generator } => {
+                if generator.is_done() {
+                    return Ok(Step::Return(Expr::Number(1)));
+                }
+                generator.peeked.replace(Some(value));
+                Ok(Step::Return(Expr::Number(0)))
+            }
+            Frame::Take { generator, n, mut items } => {
+                if generator.is_done() {
+                    return Ok(Step::Return(Expr::QExpr(items.into())));
+                }
+                items.push(value);
+                self.take_next(generator, n, items)
+            }
+            Frame::ForGen { name, generator, body, result, env } => {
+                if generator.is_done() {
+                    return Ok(Step::Return(result));
+                }
+                let local = Env::child(&env);
+                local.def(&name, value);
+                self.push(Frame::ForGenBody { name, generator, body: body.clone(), env });
+                Ok(Step::Eval(body, local))
+            }
+            Frame::Match { clauses, env } => self.match_next(value, clauses, 0, env),
+            Frame::MatchGuard { value: target, clauses, index, body, env, local } => {
+                if condition(&value)? {
+                    Ok(Step::Eval(body, local))
+                } else {
+                    self.match_next(target, clauses, index, env)
+                }
+            }
+            Frame::ForGenBody { name, generator, body, env } => self.for_gen_next(name, generator, body, value, env),
+        }
+    }
+}
+
+#[cfg(test)]
+fn run(s: &str, env: &EnvRef) -> Expr {
+    crate::token::read(s).and_then(|expr| expr.eval(env)).unwrap()
+}
+#[test]
+fn while_test() {
+    let env = Env::new();
+    let v = run("let {{i 0} {sum 0}} (while (< i 10) (set sum (+ sum i)) (set i (+ i 1))) sum", &env);
+    assert_eq!(Expr::Number(45), v);
+    // 循环次数很多也不会增加rust的调用栈
+    assert_eq!(Expr::Number(20000), run("let {{i 0}} (while {< i 20000} {set i (+ i 1)}) i", &env));
+}
+#[test]
+fn for_test() {
+    let env = Env::new();
+    assert_eq!(Expr::Number(6), run("let {{sum 0}} (for x {1 2 3} (set sum (+ sum x))) sum", &env));
+    let v = run("let {{sum 0}} (for x (range 1 100001) (set sum (+ sum 1))) sum", &env);
+    assert_eq!(Expr::Number(100000), v);
+    assert!(env.get("x").is_none());
+    // 保存在变量中的range已经是列表，结果相同
+    let v = run("let {{r (range 1 4)} {sum 0}} (for x r (set sum (+ sum x))) sum", &env);
+    assert_eq!(Expr::Number(6), v);
+}
+#[test]
+fn escape_test() {
+    let env = Env::new();
+    assert!(matches!(run("call/cc (\\ {k} {+ 1 (k 42)})", &env), Expr::Number(42)));
+    assert!(matches!(run("+ 1 (call/cc (\\ {k} {5}))", &env), Expr::Number(6)));
+    // 提前退出循环
+    let v = run("call/cc (\\ {return} {for x {1 5 8 3} (if (> x 4) (return x))})", &env);
+    assert!(matches!(v, Expr::Number(5)));
+}
+#[test]
+fn reenter_test() {
+    let env = Env::new();
+    // 保存continuation之后再次调用，会回到call/cc返回的位置
+    let v = run(
+        "let {{k 0} {n 0}} (set n (+ (call/cc (\\ {c} {do (set k c) 1})) n)) (if (< n 5) (k 1) n)",
+        &env,
+    );
+    assert!(matches!(v, Expr::Number(5)));
+}
+#[test]
+fn deep_recursion_test() {
+    let env = Env::new();
+    let v = run(
+        "letrec {{sum (\\ {n} {if (== n 0) {0} {+ n (sum (- n 1))}})}} (sum 10000)",
+        &env,
+    );
+    assert!(matches!(v, Expr::Number(50005000)));
+    let v = run(
+        "letrec {{loop (\\ {n} {if (== n 0) {7} {loop (- n 1)}})}} (loop 20000)",
+        &env,
+    );
+    assert!(matches!(v, Expr::Number(7)));
+}
+#[test]
+fn try_test() {
+    let env = Env::new();
+    // 没有出错时返回最后一个表达式的值
+    assert!(matches!(run("try 1 2 (catch _ {e} 3)", &env), Expr::Number(2)));
+    assert!(matches!(run("try (/ 1 0) (catch divide-by-zero {e} 7)", &env), Expr::Number(7)));
+    // 按照类型匹配处理错误的代码
+    let v = run("try (+ x 1) (catch divide-by-zero {e} 1) (catch unbound-symbol {e} 2)", &env);
+    assert!(matches!(v, Expr::Number(2)));
+    // 没有匹配的catch时继续向外抛出
+    let v = run("try (try (/ 1 0) (catch unbound-symbol {e} 1)) (catch _ {e} 2)", &env);
+    assert!(matches!(v, Expr::Number(2)));
+    assert!(crate::token::read("try (/ 1 0) (catch overflow {e} 1)").and_then(|expr| eval(&expr, &env)).is_err());
+}
+#[test]
+fn throw_test() {
+    let env = Env::new();
+    assert!(matches!(run("try (throw 42) (catch user {e} (error-value e))", &env), Expr::Number(42)));
+    let v = run("try (+ 1 (throw (error not-found 5))) (catch not-found {e} (+ (error-value e) 1))", &env);
+    assert!(matches!(v, Expr::Number(6)));
+    assert_eq!("not-found", run("try (throw (error not-found 5)) (catch _ {e} (error-kind e))", &env).to_string());
+    // 错误对象可以重新抛出
+    let v = run("try (try (throw (error a 1)) (catch _ {e} (throw e))) (catch a {e} 2)", &env);
+    assert!(matches!(v, Expr::Number(2)));
+}
+#[test]
+fn finally_test() {
+    let env = Env::new();
+    env.def("n", Expr::Number(0));
+    assert!(matches!(run("try 1 (finally (set n (+ n 1)))", &env), Expr::Number(1)));
+    assert!(matches!(env.get("n"), Some(Expr::Number(1))));
+    // 出错时先执行finally再向外抛出
+    let v = run("try (try (/ 1 0) (finally (set n (+ n 1)))) (catch _ {e} n)", &env);
+    assert!(matches!(v, Expr::Number(2)));
+    // 处理错误之后也会执行finally
+    let v = run("try (/ 1 0) (catch _ {e} 5) (finally (set n (+ n 1)))", &env);
+    assert!(matches!(v, Expr::Number(5)));
+    assert!(matches!(env.get("n"), Some(Expr::Number(3))));
+    // 处理错误的代码再次出错时，finally仍然执行
+    let v = run("try (try (/ 1 0) (catch _ {e} (throw 1)) (finally (set n 10))) (catch user {e} n)", &env);
+    assert!(matches!(v, Expr::Number(10)));
+}
+#[test]
+fn continuation_finally_test() {
+    let env = Env::new();
+    env.def("n", Expr::Number(0));
+    // 通过continuation离开try时也会执行finally
+    assert_eq!(Expr::Number(1), run("call/cc (\\ {k} {try (k 1) (finally (set n (+ n 1)))})", &env));
+    assert_eq!(Some(Expr::Number(1)), env.get("n"));
+    // 从内到外依次执行
+    env.set("n", Expr::Number(0));
+    let v = run("call/cc (\\ {k} {try (try (k 2) (finally (set n (+ (* n 10) 1)))) (finally (set n (+ (* n 10) 2)))})", &env);
+    assert_eq!(Expr::Number(2), v);
+    assert_eq!(Some(Expr::Number(12)), env.get("n"));
+    // 跳转的目标还在try中时，finally只在try结束时执行一次
+    env.set("n", Expr::Number(0));
+    assert_eq!(Expr::Number(5), run("try (call/cc (\\ {k} {k 5})) (finally (set n (+ n 1)))", &env));
+    assert_eq!(Some(Expr::Number(1)), env.get("n"));
+}
+#[test]
+fn error_span_test() {
+    let env = Env::new();
+    let err = crate::token::read("+ 1 (/ 4 0)").and_then(|expr| eval(&expr, &env)).unwrap_err();
+    assert_eq!("divide-by-zero", err.kind);
+    assert_eq!(Some(Span { start: 5, end: 10 }), err.span);
+    assert_eq!("{ 10 15 }", run("try (+ 1 (/ 4 0)) (catch _ {e} (error-span e))", &env).to_string());
+    let err = crate::token::read("+ 1 x").and_then(|expr| eval(&expr, &env)).unwrap_err();
+    assert_eq!("unbound-symbol: Unbound symbol \"x\" at 0..5", err.to_string());
+}
+#[test]
+fn generator_test() {
+    let env = Env::new();
+    env.def("g", run("generator (yield 1) (yield 2) 3", &env));
+    assert_eq!("{ 1 2 }", run("list (next g) (next g)", &env).to_string());
+    // 生成器结束之后抛出stop-iteration
+    assert_eq!("stop-iteration", run("try (next g) (catch _ {e} (error-kind e))", &env).to_string());
+    assert!(matches!(run("try (next g) (catch stop-iteration {e} 9)", &env), Expr::Number(9)));
+}
+#[test]
+fn lazy_generator_test() {
+    let env = Env::new();
+    env.def(
+        "nat",
+        run("\\ {} {generator (let {{i 0}} (while 1 (yield i) (set i (+ i 1))))}", &env),
+    );
+    // 无限的生成器也可以取前几个元素
+    assert_eq!("{ 0 1 2 3 4 }", run("take 5 (nat)", &env).to_string());
+    assert_eq!("{ 1 2 }", run("take 2 {1 2 3}", &env).to_string());
+    assert_eq!(run("vector 1 2", &env), run("take 2 (vector 1 2 3)", &env));
+    assert_eq!(run("vector 1 2", &env), run("take 5 (vector 1 2)", &env));
+    let v = run("let {{sum 0}} (for x (generator (for i {1 2 3} (yield (* i i)))) (set sum (+ sum x))) sum", &env);
+    assert!(matches!(v, Expr::Number(14)));
+    assert_eq!("{ 0 1 2 }", run("collect (generator (for i (range 3) (yield i)))", &env).to_string());
+}
+#[test]
+fn done_test() {
+    let env = Env::new();
+    env.def("g", run("generator (yield 1)", &env));
+    assert!(matches!(run("done? g", &env), Expr::Number(0)));
+    // done?提前取出的值不会丢失
+    assert!(matches!(run("next g", &env), Expr::Number(1)));
+    assert!(matches!(run("done? g", &env), Expr::Number(1)));
+}
+#[test]
+fn generator_type_test() {
+    let env = Env::new();
+    let message = |s: &str| crate::token::read(s).and_then(|expr| eval(&expr, &env)).unwrap_err().message;
+    assert_eq!("expected number, got string at argument 1 of take", message("take \"x\" {1}"));
+    assert_eq!("expected generator, got number at argument 2 of take", message("take 1 5"));
+    assert_eq!("expected generator, got number at argument 1 of next", message("next 1"));
+    // 条件不是函数的参数
+    assert_eq!("expected number, got string", message("if \"x\" 1 2"));
+}
+#[test]
+fn coroutine_test() {
+    let env = Env::new();
+    env.def("acc", run("generator (let {{total 0}} (while 1 (set total (+ total (yield total)))))", &env));
+    assert!(matches!(run("next acc", &env), Expr::Number(0)));
+    assert!(matches!(run("next acc 5", &env), Expr::Number(5)));
+    assert!(matches!(run("next acc 2", &env), Expr::Number(7)));
+    let err = crate::token::read("yield 1").and_then(|expr| eval(&expr, &env)).unwrap_err();
+    assert_eq!("invalid-expression", err.kind);
+}
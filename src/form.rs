@@ -0,0 +1,114 @@
+use std::rc::Rc;
+
+use crate::{
+    calc::args_count,
+    error::LispResult,
+    token::{Expr, Function, TokensError},
+    varmap::EnvRef,
+};
+
+///读取变量名
+pub fn symbol(expr: &Expr) -> LispResult<&str> {
+    match expr {
+        Expr::Symbol(name) => Ok(name),
+        _ => Err(TokensError::InvalidExpression { expected: "symbol", found: "expr" }.into()),
+    }
+}
+///检查特殊形式至少有几个参数
+pub fn min_args(tokens: &[Expr], expected: usize) -> LispResult<()> {
+    if tokens.len() < expected {
+        return Err(TokensError::ArgsCount { expected, found: tokens.len() }.into());
+    }
+    Ok(())
+}
+// 读取一个绑定`{x 1}`，返回变量名和值表达式
+fn binding(expr: &Expr) -> LispResult<(&str, &Expr)> {
+    match expr {
+        Expr::QExpr(pair) => match (pair.len(), pair.first(), pair.get(1)) {
+            (2, Some(Expr::Symbol(name)), Some(value)) => Ok((name, value)),
+            _ => Err(TokensError::InvalidExpression { expected: "{name value}", found: "qexpr" }.into()),
+        },
+        _ => Err(TokensError::InvalidExpression { expected: "{name value}", found: "expr" }.into()),
+    }
+}
+///读取绑定列表`{{x 1} {y 2}}`
+pub fn bindings(expr: &Expr) -> LispResult<Vec<(&str, &Expr)>> {
+    match expr {
+        Expr::QExpr(pairs) => pairs.iter().map(binding).collect(),
+        _ => Err(TokensError::InvalidExpression { expected: "qexpr", found: "expr" }.into()),
+    }
+}
+///创建函数 `\ {x y} {+ x y}`
+pub fn lambda(tokens: &[Expr], env: &EnvRef) -> LispResult<Expr> {
+    args_count(tokens, 2)?;
+    let params = match &tokens[0] {
+        Expr::QExpr(params) => params
+            .iter()
+            .map(|i| symbol(i).map(|i| i.to_owned()))
+            .collect::<LispResult<Vec<String>>>()?,
+        _ => return Err(TokensError::InvalidExpression { expected: "qexpr", found: "expr" }.into()),
+    };
+    Ok(Expr::Function(Rc::new(Function {
+        params,
+        body: tokens[1].to_code()?,
+        env: env.clone(),
+    })))
+}
+#[cfg(test)]
+fn run(s: &str, env: &EnvRef) -> Expr {
+    crate::token::read(s).and_then(|expr| expr.eval(env)).unwrap()
+}
+#[test]
+fn let_test() {
+    let env = crate::varmap::Env::new();
+    let v = run("let {{x 1} {y 2}} (+ x y)", &env);
+    assert!(matches!(v, Expr::Number(3)));
+    // 局部变量不会出现在全局作用域
+    assert!(env.get("x").is_none());
+    // let中的值在外层作用域中计算
+    env.def("x", Expr::Number(10));
+    let v = run("let {{x 1} {y x}} (+ x y)", &env);
+    assert!(matches!(v, Expr::Number(11)));
+}
+#[test]
+fn let_star_test() {
+    let env = crate::varmap::Env::new();
+    let v = run("let* {{x 1} {y (+ x 1)} {x (* y 10)}} (+ x y)", &env);
+    assert!(matches!(v, Expr::Number(22)));
+    assert!(env.get("y").is_none());
+}
+#[test]
+fn letrec_test() {
+    let env = crate::varmap::Env::new();
+    let v = run(
+        "letrec {{even? (\\ {n} {if (== n 0) {1} {odd? (- n 1)}})} {odd? (\\ {n} {if (== n 0) {0} {even? (- n 1)}})}} (even? 10)",
+        &env,
+    );
+    assert!(matches!(v, Expr::Number(1)));
+    let v = run(
+        "letrec {{fact (\\ {n} {if (== n 0) {1} {* n (fact (- n 1))}})}} (fact 5)",
+        &env,
+    );
+    assert!(matches!(v, Expr::Number(120)));
+    assert!(env.get("fact").is_none());
+}
+// 已知的泄漏：letrec中的函数和子作用域互相引用，计算结束之后子作用域仍然存在
+#[test]
+fn letrec_leak_test() {
+    let env = crate::varmap::Env::new();
+    let value: Rc<str> = "v".into();
+    env.def("v", Expr::Str(value.clone()));
+    assert_eq!(2, Rc::strong_count(&value));
+    run("let {{f (\\ {n} {n})} {s v}} (f 1)", &env);
+    assert_eq!(2, Rc::strong_count(&value));
+    run("letrec {{f (\\ {n} {n})} {s v}} (f 1)", &env);
+    assert_eq!(3, Rc::strong_count(&value));
+}
+#[test]
+fn do_test() {
+    let env = crate::varmap::Env::new();
+    let v = run("do (+ 1 2) (let {{x 4}} (* x x))", &env);
+    assert!(matches!(v, Expr::Number(16)));
+    let v = run("begin {1 2}", &env);
+    assert_eq!("{ 1 2 }", v.to_string());
+}
@@ -0,0 +1,127 @@
+use crate::{
+    error::LispResult,
+    form::{lambda, min_args, symbol},
+    token::{Expr, Function, TokensError},
+    varmap::EnvRef,
+};
+
+///定义宏 `defmacro unless {c & body} {`{if ,c {} {do ,@body}}}`，宏的参数不会被计算
+pub fn defmacro(tokens: &[Expr], env: &EnvRef) -> LispResult<Expr> {
+    min_args(tokens, 1)?;
+    let name = symbol(&tokens[0])?;
+    let func = match lambda(&tokens[1..], env)? {
+        Expr::Function(func) => func,
+        _ => unreachable!(),
+    };
+    let value = Expr::Macro(func);
+    env.def(name, value.clone());
+    Ok(value)
+}
+///调用宏，返回展开后的代码
+pub fn expand_call(func: &Function, args: &[Expr]) -> LispResult<Expr> {
+    let local = Expr::bind(func, args.to_vec())?;
+    func.body.eval(&local)?.to_code()
+}
+///展开表达式中所有的宏，在计算之前调用。qexpr和quasiquote中的内容是数据，不会展开
+pub fn expand(expr: &Expr, env: &EnvRef) -> LispResult<Expr> {
+    match expr {
+        Expr::Operator(op, args, span) => {
+            // 展开结果中可能还有宏
+            let expanded = match env.get(op) {
+                Some(Expr::Macro(func)) => expand_call(&func, args),
+                Some(Expr::Syntax(rules)) => rules.expand(op, args, env),
+                _ => {
+                    if op == "quasiquote" {
+                        return Ok(expr.to_owned());
+                    }
+                    let args = args.iter().map(|i| expand(i, env)).collect::<LispResult<_>>()?;
+                    return Ok(Expr::Operator(op.to_owned(), args, *span));
+                }
+            };
+            expand(&expanded.map_err(|err| err.with_span(*span))?, env)
+        }
+        _ => Ok(expr.to_owned()),
+    }
+}
+///`macroexpand (unless 1 2)`，返回展开后的代码而不计算
+pub fn macroexpand(tokens: &[Expr], env: &EnvRef) -> LispResult<Expr> {
+    min_args(tokens, 1)?;
+    expand(&tokens[0].to_code()?, env)
+}
+///`` `{+ ,x ,@xs} ``，只计算`,`和`,@`标记的表达式
+pub fn quasiquote(tokens: &[Expr], env: &EnvRef) -> LispResult<Expr> {
+    min_args(tokens, 1)?;
+    template(&tokens[0], env)
+}
+fn template(expr: &Expr, env: &EnvRef) -> LispResult<Expr> {
+    match expr {
+        Expr::Operator(op, args, _) if op == "unquote" => args[0].eval(env),
+        Expr::Operator(op, args, span) => Ok(Expr::Operator(op.to_owned(), template_list(args, env)?, *span)),
+        Expr::QExpr(items) => Ok(Expr::QExpr(template_list(&items.to_vec(), env)?.into())),
+        _ => Ok(expr.to_owned()),
+    }
+}
+// `,@`的结果必须是qexpr，里面的元素会被插入到列表中
+fn template_list(items: &[Expr], env: &EnvRef) -> LispResult<Vec<Expr>> {
+    let mut result = Vec::new();
+    for i in items {
+        match i {
+            Expr::Operator(op, args, _) if op == "unquote-splicing" => match args[0].eval(env)? {
+                Expr::QExpr(values) => result.extend(values.iter().cloned()),
+                _ => return Err(TokensError::InvalidExpression { expected: "qexpr", found: "expr" }.into()),
+            },
+            _ => result.push(template(i, env)?),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+fn run(s: &str, env: &EnvRef) -> Expr {
+    crate::token::read(s).and_then(|expr| expand(&expr, env)).and_then(|expr| expr.eval(env)).unwrap()
+}
+#[test]
+fn quasiquote_test() {
+    let env = crate::varmap::Env::new();
+    env.def("x", Expr::Number(1));
+    env.def("xs", Expr::QExpr(vec![Expr::Number(2), Expr::Number(3)].into()));
+    assert_eq!("{ + 1 { 2 3 } }", run("`{+ ,x ,xs}", &env).to_string());
+    assert_eq!("{ + 1 2 3 }", run("`{+ ,x ,@xs}", &env).to_string());
+    assert_eq!("{ a { 1 } }", run("`{a {,x}}", &env).to_string());
+}
+#[test]
+fn defmacro_test() {
+    let env = crate::varmap::Env::new();
+    run("defmacro unless {c & body} {`{if ,c {} {do ,@body}}}", &env);
+    assert!(matches!(run("unless (== 1 2) 5 6", &env), Expr::Number(6)));
+    assert_eq!("{ }", run("unless (== 1 1) 5 6", &env).to_string());
+    // 参数不会被计算，没有定义的变量也不会报错
+    assert_eq!("{ }", run("unless 1 undefined", &env).to_string());
+}
+#[test]
+fn macroexpand_test() {
+    let env = crate::varmap::Env::new();
+    run("defmacro swap {a b} {`{,b ,a}}", &env);
+    run("defmacro unless {c & body} {`{if ,c {} {do ,@body}}}", &env);
+    assert_eq!("(- 10)", run("macroexpand (swap 10 -)", &env).to_string());
+    // qexpr中的宏在计算的时候才会展开
+    assert_eq!(
+        "(if (> x 1) { } { do (swap 1 -) x })",
+        run("macroexpand (unless (> x 1) (swap 1 -) x)", &env).to_string()
+    );
+    assert_eq!(
+        "(+ (- 2) 1)",
+        run("macroexpand (+ (swap 2 -) 1)", &env).to_string()
+    );
+    assert_eq!(
+        "(if 0 { } { do 1 })",
+        run("macroexpand {unless 0 1}", &env).to_string()
+    );
+}
+#[test]
+fn macro_in_function_test() {
+    let env = crate::varmap::Env::new();
+    run("defmacro unless {c & body} {`{if ,c {} {do ,@body}}}", &env);
+    let v = run("let {{f (\\ {n} {unless (== n 0) (* n 2)})}} (f 4)", &env);
+    assert!(matches!(v, Expr::Number(8)));
+}
@@ -8,6 +8,20 @@ mod utils;
 mod ac;
 mod varmap;
 mod calc;
+mod error;
+mod eval;
+mod form;
+mod macros;
+mod syntax;
+mod pattern;
+mod map;
+mod vector;
+mod persistent;
+mod string;
+mod record;
+mod types;
+mod equal;
+mod serial;
 
 fn main() {
     let terminal=Terminal::new();
@@ -0,0 +1,131 @@
+// 键的哈希不依赖函数和生成器等值内部可变的部分
+#![allow(clippy::mutable_key_type)]
+use crate::{
+    calc::{arg_type, args_count},
+    error::LispResult,
+    form::min_args,
+    persistent::{Hamt, List},
+    string::str_contains,
+    token::{Expr, TokensError},
+};
+
+///哈希表的键，按结构计算哈希和比较，`{1 2}`和`{1 2}`是同一个键
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Key(Expr);
+///不可变的哈希表，修改时返回共享结构的新哈希表
+pub type Map = Hamt<Key, Expr>;
+
+impl Key {
+    ///函数等没有结构的值按引用作为键
+    pub fn new(expr: &Expr) -> Key {
+        Key(expr.to_owned())
+    }
+    pub fn expr(&self) -> &Expr {
+        &self.0
+    }
+}
+///按键排序的所有键值对，保证输出和`keys`的顺序固定
+pub fn entries(map: &Map) -> Vec<(&Key, &Expr)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+///由键值对创建哈希表，后面的键覆盖前面的键
+pub fn from_pairs(items: &[Expr]) -> LispResult<Map> {
+    if !items.len().is_multiple_of(2) {
+        return Err(TokensError::InvalidExpression { expected: "key value pairs", found: "odd number of items" }.into());
+    }
+    items.chunks(2).map(|pair| Ok((Key::new(&pair[0]), pair[1].to_owned()))).collect()
+}
+fn extra_map(expr: &Expr, index: usize) -> LispResult<&Map> {
+    match expr {
+        Expr::Map(map) => Ok(map),
+        _ => Err(arg_type("map", expr, index)),
+    }
+}
+///`hash-map k v ...`，键和值都会被计算
+pub fn hash_map(tokens: &[Expr]) -> LispResult<Expr> {
+    Ok(Expr::Map(from_pairs(tokens)?))
+}
+///`get m k`，键不存在时返回默认值或者`{}`
+pub fn get(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 2)?;
+    let map = extra_map(&tokens[0], 0)?;
+    let default = tokens.get(2).cloned().unwrap_or(Expr::QExpr(List::new()));
+    Ok(map.get(&Key::new(&tokens[1])).cloned().unwrap_or(default))
+}
+///`assoc m k v ...`，返回添加了键值对的新哈希表
+pub fn assoc(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 1)?;
+    let map = extra_map(&tokens[0], 0)?;
+    let map = from_pairs(&tokens[1..])?.iter().fold(map.to_owned(), |map, (k, v)| map.insert(k.to_owned(), v.to_owned()));
+    Ok(Expr::Map(map))
+}
+///`dissoc m k ...`，返回删除了这些键的新哈希表
+pub fn dissoc(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 1)?;
+    let mut map = extra_map(&tokens[0], 0)?.to_owned();
+    for key in &tokens[1..] {
+        map = map.remove(&Key::new(key));
+    }
+    Ok(Expr::Map(map))
+}
+pub fn keys(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    let map = extra_map(&tokens[0], 0)?;
+    Ok(Expr::QExpr(entries(map).into_iter().map(|(k, _)| k.0.to_owned()).collect()))
+}
+pub fn vals(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    let map = extra_map(&tokens[0], 0)?;
+    Ok(Expr::QExpr(entries(map).into_iter().map(|(_, v)| v.to_owned()).collect()))
+}
+///`contains? m k`，第一个参数是字符串时查找子串
+pub fn contains(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 2)?;
+    if let Expr::Str(_) = tokens[0] {
+        return str_contains(tokens);
+    }
+    let map = extra_map(&tokens[0], 0)?;
+    Ok(Expr::Number(map.contains_key(&Key::new(&tokens[1])) as u32))
+}
+///`merge a b ...`，相同的键使用后面哈希表中的值
+pub fn merge(tokens: &[Expr]) -> LispResult<Expr> {
+    let mut map = Map::new();
+    for (i, token) in tokens.iter().enumerate() {
+        map = extra_map(token, i)?.iter().fold(map, |map, (k, v)| map.insert(k.to_owned(), v.to_owned()));
+    }
+    Ok(Expr::Map(map))
+}
+
+#[cfg(test)]
+fn run(s: &str) -> Expr {
+    crate::token::read(s).and_then(|expr| expr.eval(&crate::varmap::Env::new())).unwrap()
+}
+#[test]
+fn literal_test() {
+    assert_eq!("#{ a 1 b 2 }", run("#{b 2 a 1}").to_string());
+    assert_eq!("{ #{ a { 1 } } }", run("list #{a {1}}").to_string());
+    assert!(matches!(run("get #{a 1 {1 2} 3} {1 2}"), Expr::Number(3)));
+    assert_eq!("{ }", run("get #{a 1} `b").to_string());
+    assert!(matches!(run("get #{a 1} `b 7"), Expr::Number(7)));
+    assert!(matches!(run("contains? #{5 {x}} 5"), Expr::Number(1)));
+    assert!(matches!(run("get #{\"中\" 1} \"中\""), Expr::Number(1)));
+}
+#[test]
+fn update_test() {
+    assert_eq!("#{ 1 2 a 3 }", run("assoc #{a 1} `a 3 1 2").to_string());
+    assert_eq!("#{ b 2 }", run("dissoc #{a 1 b 2} `a").to_string());
+    assert_eq!("{ a b }", run("keys #{b 2 a 1}").to_string());
+    assert_eq!("{ 1 2 }", run("vals #{b 2 a 1}").to_string());
+    assert_eq!("#{ a 1 b 3 c 4 }", run("merge #{a 1 b 2} #{b 3 c 4}").to_string());
+    // 键的结构相同时是同一个键，和插入顺序无关
+    assert!(matches!(run("get (hash-map #{a 1 b 2} 9) #{b 2 a 1}"), Expr::Number(9)));
+}
+#[test]
+fn type_error_test() {
+    let env = crate::varmap::Env::new();
+    let message = |s: &str| crate::token::read(s).and_then(|expr| expr.eval(&env)).unwrap_err().message;
+    assert_eq!("expected map, got qexpr at argument 1 of get", message("get {1} 1"));
+    assert_eq!("expected map, got number at argument 2 of merge", message("merge #{} 1"));
+}
@@ -0,0 +1,125 @@
+use crate::{
+    error::LispResult,
+    persistent::List,
+    record::RecordType,
+    token::{Expr, TokensError},
+    varmap::EnvRef,
+};
+
+const WILDCARD: &str = "_";
+const REST: &str = "&";
+const GUARD: &str = "when";
+
+///`match`的一条分支，`{pattern body}`或者`{pattern when guard body}`
+#[derive(Debug, Clone)]
+pub struct Clause {
+    pub pattern: Expr,
+    pub guard: Option<Expr>,
+    pub body: Expr,
+}
+
+///解析`match`的所有分支，条件和分支体转换为代码
+pub fn clauses(tokens: &[Expr]) -> LispResult<Vec<Clause>> {
+    tokens
+        .iter()
+        .map(|clause| match clause {
+            Expr::QExpr(items) => match items.to_vec().as_slice() {
+                [pattern, body] => Ok(Clause { pattern: pattern.to_owned(), guard: None, body: body.to_code()? }),
+                [pattern, Expr::Symbol(when), guard, body] if when == GUARD => Ok(Clause {
+                    pattern: pattern.to_owned(),
+                    guard: Some(guard.to_code()?),
+                    body: body.to_code()?,
+                }),
+                _ => Err(TokensError::InvalidExpression { expected: "{pattern body}", found: "clause" }.into()),
+            },
+            _ => Err(TokensError::InvalidExpression { expected: "qexpr", found: "expr" }.into()),
+        })
+        .collect()
+}
+
+///匹配模式，成功时把变量绑定放入bindings
+///`_`匹配任意值，符号绑定变量，`` `a ``匹配符号本身，`{x & xs}`匹配剩余的元素，
+///`(point x y)`中的类型在env中查找
+pub fn match_pattern(pattern: &Expr, value: &Expr, env: &EnvRef, bindings: &mut Vec<(String, Expr)>) -> bool {
+    match (pattern, value) {
+        (Expr::Symbol(name), _) if name == WILDCARD => true,
+        (Expr::Symbol(name), _) => {
+            bindings.push((name.to_owned(), value.to_owned()));
+            true
+        }
+        (Expr::Number(p), Expr::Number(v)) => p == v,
+        (Expr::Str(p), Expr::Str(v)) => p == v,
+        (Expr::Char(p), Expr::Char(v)) => p == v,
+        (Expr::Operator(op, quoted, _), Expr::Symbol(v)) if op == "quasiquote" => {
+            matches!(quoted.as_slice(), [Expr::Symbol(p)] if p == v)
+        }
+        // `(point x y)`按字段的顺序匹配记录，point是当前作用域中的构造函数
+        (Expr::Operator(name, patterns, _), Expr::Record(record)) => {
+            is_record_type(name, &record.ty, env)
+                && patterns.len() == record.values.len()
+                && patterns.iter().zip(&record.values).all(|(p, v)| match_pattern(p, v, env, bindings))
+        }
+        (Expr::QExpr(patterns), Expr::QExpr(values)) => match_list(&patterns.to_vec(), values, env, bindings),
+        _ => false,
+    }
+}
+// 模式中的名字是不是这个记录类型的构造函数
+fn is_record_type(name: &str, ty: &RecordType, env: &EnvRef) -> bool {
+    match env.get(name) {
+        Some(Expr::Native(native)) => native.record.as_ref().is_some_and(|i| i.id == ty.id),
+        _ => false,
+    }
+}
+fn match_list(patterns: &[Expr], values: &List, env: &EnvRef, bindings: &mut Vec<(String, Expr)>) -> bool {
+    let rest = patterns.iter().position(|i| matches!(i, Expr::Symbol(name) if name == REST));
+    let (fixed, rest) = match rest {
+        Some(index) => (&patterns[..index], patterns.get(index + 1)),
+        None => (patterns, None),
+    };
+    if values.len() < fixed.len() || (rest.is_none() && values.len() != fixed.len()) {
+        return false;
+    }
+    if !fixed.iter().zip(values).all(|(p, v)| match_pattern(p, v, env, bindings)) {
+        return false;
+    }
+    match rest {
+        // 剩余的元素和原来的列表共享节点
+        Some(rest) => {
+            let tail = fixed.iter().fold(values.to_owned(), |list, _| list.tail());
+            match_pattern(rest, &Expr::QExpr(tail), env, bindings)
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+fn run(s: &str, env: &crate::varmap::EnvRef) -> LispResult<Expr> {
+    crate::token::read(s).and_then(|expr| expr.eval(env))
+}
+#[test]
+fn destructure_test() {
+    let env = crate::varmap::Env::new();
+    let v = run("match {1 2 3} {{x & xs} {list x xs}}", &env).unwrap();
+    assert_eq!("{ 1 { 2 3 } }", v.to_string());
+    let v = run("match {1 {2 3}} {{a {b c}} (+ a b c)}", &env).unwrap();
+    assert!(matches!(v, Expr::Number(6)));
+    let v = run("match {} {{x & xs} 1} {{} 0}", &env).unwrap();
+    assert!(matches!(v, Expr::Number(0)));
+    let v = run("match {\"ok\" 2} {{\"err\" _} 0} {{\"ok\" n} n}", &env).unwrap();
+    assert!(matches!(v, Expr::Number(2)));
+    let v = run("match 5 {3 0} {_ 1}", &env).unwrap();
+    assert!(matches!(v, Expr::Number(1)));
+    let v = run("match {add 1 2} {{`sub a b} (- a b)} {{`add a b} (+ a b)}", &env).unwrap();
+    assert!(matches!(v, Expr::Number(3)));
+}
+#[test]
+fn guard_test() {
+    let env = crate::varmap::Env::new();
+    let v = run("match 7 {n when (< n 5) 0} {n when (< n 10) 1} {_ 2}", &env).unwrap();
+    assert!(matches!(v, Expr::Number(1)));
+    // 用match代替head和tail
+    let v = run("letrec {{len (\\ {l} {match l {{} 0} {{_ & xs} (+ 1 (len xs))}})}} (len {1 2 3})", &env).unwrap();
+    assert!(matches!(v, Expr::Number(3)));
+    let err = run("match {1 2} {{x} x}", &env).unwrap_err();
+    assert_eq!("match-error", err.kind);
+}
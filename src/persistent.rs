@@ -0,0 +1,545 @@
+//! 持久化的数据结构，修改时返回新的值，新旧两个值共享没有改变的部分
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use crate::token::Expr;
+
+///单向链表，`tail`和`cons`不需要复制元素
+#[derive(Clone, Default)]
+pub struct List {
+    head: Option<Rc<Cons>>,
+    len: usize,
+}
+struct Cons {
+    value: Expr,
+    next: Option<Rc<Cons>>,
+}
+impl List {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn first(&self) -> Option<&Expr> {
+        self.head.as_ref().map(|node| &node.value)
+    }
+    ///去掉第一个元素，和原来的链表共享剩下的节点
+    pub fn tail(&self) -> List {
+        match &self.head {
+            Some(node) => List { head: node.next.clone(), len: self.len - 1 },
+            None => List::new(),
+        }
+    }
+    ///在前面添加一个元素
+    pub fn cons(&self, value: Expr) -> List {
+        List { head: Some(Rc::new(Cons { value, next: self.head.clone() })), len: self.len + 1 }
+    }
+    pub fn get(&self, index: usize) -> Option<&Expr> {
+        self.iter().nth(index)
+    }
+    pub fn iter(&self) -> ListIter<'_> {
+        ListIter { node: self.head.as_deref() }
+    }
+    ///复制到Vec中，用于按切片匹配
+    pub fn to_vec(&self) -> Vec<Expr> {
+        self.iter().cloned().collect()
+    }
+    ///是否是同一个链表，空链表都相同
+    pub fn ptr_eq(&self, other: &List) -> bool {
+        match (&self.head, &other.head) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+// 链表很长时递归释放节点会栈溢出，只有唯一引用的节点才继续向后释放
+impl Drop for List {
+    fn drop(&mut self) {
+        let mut next = self.head.take();
+        while let Some(node) = next {
+            match Rc::try_unwrap(node) {
+                Ok(mut node) => next = node.next.take(),
+                Err(_) => break,
+            }
+        }
+    }
+}
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+impl From<Vec<Expr>> for List {
+    fn from(value: Vec<Expr>) -> Self {
+        value.into_iter().rev().fold(List::new(), |list, i| list.cons(i))
+    }
+}
+impl FromIterator<Expr> for List {
+    fn from_iter<T: IntoIterator<Item = Expr>>(iter: T) -> Self {
+        iter.into_iter().collect::<Vec<_>>().into()
+    }
+}
+pub struct ListIter<'a> {
+    node: Option<&'a Cons>,
+}
+impl<'a> Iterator for ListIter<'a> {
+    type Item = &'a Expr;
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.node?;
+        self.node = node.next.as_deref();
+        Some(&node.value)
+    }
+}
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a Expr;
+    type IntoIter = ListIter<'a>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+///32路前缀树实现的向量，修改时只复制从根到叶子的一条路径
+///每个节点都是满的，不是RRB向量，拼接两个向量时需要逐个添加元素
+#[derive(Clone)]
+pub struct TrieVector {
+    root: Rc<VNode>,
+    // 树的高度，叶子在shift为0的一层
+    shift: u32,
+    // 树中元素的个数
+    size: usize,
+    // `subvec`得到的向量和原来的向量共享同一棵树，只记录范围
+    start: usize,
+    end: usize,
+}
+#[derive(Clone)]
+enum VNode {
+    Branch(Vec<Rc<VNode>>),
+    Leaf(Vec<Expr>),
+}
+impl Default for TrieVector {
+    fn default() -> Self {
+        Self { root: Rc::new(VNode::Leaf(Vec::new())), shift: 0, size: 0, start: 0, end: 0 }
+    }
+}
+impl TrieVector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn get(&self, index: usize) -> Option<&Expr> {
+        if index >= self.len() {
+            return None;
+        }
+        let index = self.start + index;
+        self.leaf(index).get(index & MASK)
+    }
+    // 树中下标为index的元素所在的叶子
+    fn leaf(&self, index: usize) -> &[Expr] {
+        let mut node = &self.root;
+        let mut shift = self.shift;
+        loop {
+            match node.as_ref() {
+                VNode::Branch(children) => node = &children[(index >> shift) & MASK],
+                VNode::Leaf(items) => return items,
+            }
+            shift = shift.saturating_sub(BITS);
+        }
+    }
+    ///修改一个元素，下标越界时返回None
+    pub fn set(&self, index: usize, value: Expr) -> Option<TrieVector> {
+        if index >= self.len() {
+            return None;
+        }
+        let root = Rc::new(Self::set_node(&self.root, self.shift, self.start + index, value));
+        Some(TrieVector { root, ..self.clone() })
+    }
+    fn set_node(node: &VNode, shift: u32, index: usize, value: Expr) -> VNode {
+        match node {
+            VNode::Branch(children) => {
+                let mut children = children.clone();
+                let i = (index >> shift) & MASK;
+                children[i] = Rc::new(Self::set_node(&children[i], shift - BITS, index, value));
+                VNode::Branch(children)
+            }
+            VNode::Leaf(items) => {
+                let mut items = items.clone();
+                items[index & MASK] = value;
+                VNode::Leaf(items)
+            }
+        }
+    }
+    ///在末尾添加一个元素
+    pub fn push(&self, value: Expr) -> TrieVector {
+        // 截取过的向量末尾之后还有元素时直接覆盖
+        if self.end < self.size {
+            let root = Rc::new(Self::set_node(&self.root, self.shift, self.end, value));
+            return TrieVector { root, end: self.end + 1, ..self.clone() };
+        }
+        // 树已经满了，增加一层
+        if self.size == WIDTH << self.shift {
+            let path = Self::new_path(self.shift, value);
+            let root = Rc::new(VNode::Branch(vec![self.root.clone(), Rc::new(path)]));
+            return TrieVector { root, shift: self.shift + BITS, size: self.size + 1, start: self.start, end: self.end + 1 };
+        }
+        let root = Rc::new(Self::push_node(&self.root, self.shift, self.size, value));
+        TrieVector { root, size: self.size + 1, end: self.end + 1, ..self.clone() }
+    }
+    fn push_node(node: &VNode, shift: u32, index: usize, value: Expr) -> VNode {
+        match node {
+            VNode::Branch(children) => {
+                let mut children = children.clone();
+                let i = (index >> shift) & MASK;
+                match children.get(i) {
+                    Some(child) => children[i] = Rc::new(Self::push_node(child, shift - BITS, index, value)),
+                    None => children.push(Rc::new(Self::new_path(shift - BITS, value))),
+                }
+                VNode::Branch(children)
+            }
+            VNode::Leaf(items) => {
+                let mut items = items.clone();
+                items.push(value);
+                VNode::Leaf(items)
+            }
+        }
+    }
+    // 创建只有一个元素的子树
+    fn new_path(shift: u32, value: Expr) -> VNode {
+        if shift == 0 {
+            VNode::Leaf(vec![value])
+        } else {
+            VNode::Branch(vec![Rc::new(Self::new_path(shift - BITS, value))])
+        }
+    }
+    ///截取`[start, end)`，和原来的向量共享同一棵树
+    pub fn slice(&self, start: usize, end: usize) -> Option<TrieVector> {
+        if start > end || end > self.len() {
+            return None;
+        }
+        Some(TrieVector { start: self.start + start, end: self.start + end, ..self.clone() })
+    }
+    ///按叶子遍历，每个叶子只从根查找一次
+    pub fn iter(&self) -> TrieVectorIter<'_> {
+        TrieVectorIter { vector: self, index: self.start, leaf: [].iter() }
+    }
+    ///是否是同一棵树上的同一个范围
+    pub fn ptr_eq(&self, other: &TrieVector) -> bool {
+        Rc::ptr_eq(&self.root, &other.root) && self.start == other.start && self.end == other.end
+    }
+}
+impl fmt::Debug for TrieVector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+impl FromIterator<Expr> for TrieVector {
+    fn from_iter<T: IntoIterator<Item = Expr>>(iter: T) -> Self {
+        iter.into_iter().fold(TrieVector::new(), |v, i| v.push(i))
+    }
+}
+pub struct TrieVectorIter<'a> {
+    vector: &'a TrieVector,
+    // 下一个叶子中第一个元素在树中的下标
+    index: usize,
+    leaf: std::slice::Iter<'a, Expr>,
+}
+impl<'a> Iterator for TrieVectorIter<'a> {
+    type Item = &'a Expr;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(item) = self.leaf.next() {
+            return Some(item);
+        }
+        if self.index >= self.vector.end {
+            return None;
+        }
+        // 截取过的向量只遍历范围内的部分
+        let offset = self.index & MASK;
+        let len = (WIDTH - offset).min(self.vector.end - self.index);
+        self.leaf = self.vector.leaf(self.index)[offset..offset + len].iter();
+        self.index += len;
+        self.leaf.next()
+    }
+}
+
+///哈希数组映射前缀树，每层使用哈希值的5位
+pub struct Hamt<K, V> {
+    root: Rc<HNode<K, V>>,
+    len: usize,
+}
+struct HNode<K, V> {
+    // 哪些位置上有元素
+    bitmap: u32,
+    entries: Vec<Entry<K, V>>,
+}
+enum Entry<K, V> {
+    Leaf(u64, K, V),
+    // 哈希值完全相同的键
+    Collision(u64, Vec<(K, V)>),
+    Node(Rc<HNode<K, V>>),
+}
+// 派生的Clone会要求K和V实现Clone，这里只复制Rc
+impl<K, V> Clone for Hamt<K, V> {
+    fn clone(&self) -> Self {
+        Self { root: self.root.clone(), len: self.len }
+    }
+}
+impl<K: Clone, V: Clone> Clone for Entry<K, V> {
+    fn clone(&self) -> Self {
+        match self {
+            Entry::Leaf(hash, k, v) => Entry::Leaf(*hash, k.clone(), v.clone()),
+            Entry::Collision(hash, items) => Entry::Collision(*hash, items.clone()),
+            Entry::Node(node) => Entry::Node(node.clone()),
+        }
+    }
+}
+impl<K, V> Default for Hamt<K, V> {
+    fn default() -> Self {
+        Self { root: Rc::new(HNode { bitmap: 0, entries: Vec::new() }), len: 0 }
+    }
+}
+fn hash_of<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+// 哈希值在这一层对应的位置
+fn slot(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) as usize & MASK)
+}
+impl<K: Hash + Eq + Clone, V: Clone> HNode<K, V> {
+    fn position(&self, bit: u32) -> usize {
+        (self.bitmap & (bit - 1)).count_ones() as usize
+    }
+    fn get(&self, hash: u64, key: &K, shift: u32) -> Option<&V> {
+        let bit = slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        match &self.entries[self.position(bit)] {
+            Entry::Leaf(_, k, v) => (k == key).then_some(v),
+            Entry::Collision(_, items) => items.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            Entry::Node(node) => node.get(hash, key, shift + BITS),
+        }
+    }
+    // 返回新的节点和是否增加了一个键
+    fn insert(&self, hash: u64, key: K, value: V, shift: u32) -> (HNode<K, V>, bool) {
+        let bit = slot(hash, shift);
+        let pos = self.position(bit);
+        let mut entries = self.entries.clone();
+        if self.bitmap & bit == 0 {
+            entries.insert(pos, Entry::Leaf(hash, key, value));
+            return (HNode { bitmap: self.bitmap | bit, entries }, true);
+        }
+        let (entry, added) = match &entries[pos] {
+            Entry::Leaf(h, k, _) if *k == key => (Entry::Leaf(*h, key, value), false),
+            Entry::Leaf(h, k, v) if *h == hash => (Entry::Collision(hash, vec![(k.clone(), v.clone()), (key, value)]), true),
+            Entry::Leaf(h, ..) => (Self::split(*h, entries[pos].clone(), hash, key, value, shift + BITS), true),
+            Entry::Collision(h, items) if *h == hash => {
+                let mut items = items.clone();
+                let added = match items.iter().position(|(k, _)| *k == key) {
+                    Some(i) => {
+                        items[i] = (key, value);
+                        false
+                    }
+                    None => {
+                        items.push((key, value));
+                        true
+                    }
+                };
+                (Entry::Collision(hash, items), added)
+            }
+            Entry::Collision(h, _) => (Self::split(*h, entries[pos].clone(), hash, key, value, shift + BITS), true),
+            Entry::Node(node) => {
+                let (node, added) = node.insert(hash, key, value, shift + BITS);
+                (Entry::Node(Rc::new(node)), added)
+            }
+        };
+        entries[pos] = entry;
+        (HNode { bitmap: self.bitmap, entries }, added)
+    }
+    // 两个哈希值不同的元素落在同一个位置，放到下一层中
+    fn split(old_hash: u64, old: Entry<K, V>, hash: u64, key: K, value: V, shift: u32) -> Entry<K, V> {
+        let node = HNode { bitmap: slot(old_hash, shift), entries: vec![old] };
+        let (node, _) = node.insert(hash, key, value, shift);
+        Entry::Node(Rc::new(node))
+    }
+    // 没有找到时返回None，删除后节点为空时返回的节点没有元素
+    fn remove(&self, hash: u64, key: &K, shift: u32) -> Option<HNode<K, V>> {
+        let bit = slot(hash, shift);
+        if self.bitmap & bit == 0 {
+            return None;
+        }
+        let pos = self.position(bit);
+        let mut entries = self.entries.clone();
+        let mut bitmap = self.bitmap;
+        match &self.entries[pos] {
+            Entry::Leaf(_, k, _) if k == key => {
+                entries.remove(pos);
+                bitmap &= !bit;
+            }
+            Entry::Leaf(..) => return None,
+            Entry::Collision(h, items) => {
+                let mut items = items.clone();
+                let i = items.iter().position(|(k, _)| k == key)?;
+                items.remove(i);
+                entries[pos] = match items.len() {
+                    1 => {
+                        let (k, v) = items.remove(0);
+                        Entry::Leaf(*h, k, v)
+                    }
+                    _ => Entry::Collision(*h, items),
+                };
+            }
+            Entry::Node(node) => {
+                let node = node.remove(hash, key, shift + BITS)?;
+                match node.entries.as_slice() {
+                    [] => {
+                        entries.remove(pos);
+                        bitmap &= !bit;
+                    }
+                    // 子节点只剩一个元素时放回这一层
+                    [Entry::Leaf(..)] | [Entry::Collision(..)] => entries[pos] = node.entries[0].clone(),
+                    _ => entries[pos] = Entry::Node(Rc::new(node)),
+                }
+            }
+        }
+        Some(HNode { bitmap, entries })
+    }
+}
+impl<K, V> Hamt<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.root, &other.root)
+    }
+    pub fn iter(&self) -> HamtIter<'_, K, V> {
+        HamtIter { stack: vec![self.root.entries.iter()], collision: None }
+    }
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+impl<K: Hash + Eq + Clone, V: Clone> Hamt<K, V> {
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.root.get(hash_of(key), key, 0)
+    }
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+    ///添加或者替换一个键，返回新的哈希表
+    pub fn insert(&self, key: K, value: V) -> Self {
+        let (root, added) = self.root.insert(hash_of(&key), key, value, 0);
+        Self { root: Rc::new(root), len: self.len + added as usize }
+    }
+    ///删除一个键，返回新的哈希表
+    pub fn remove(&self, key: &K) -> Self {
+        match self.root.remove(hash_of(key), key, 0) {
+            Some(root) => Self { root: Rc::new(root), len: self.len - 1 },
+            None => self.clone(),
+        }
+    }
+}
+pub struct HamtIter<'a, K, V> {
+    // 每一层还没有遍历的元素
+    stack: Vec<std::slice::Iter<'a, Entry<K, V>>>,
+    collision: Option<std::slice::Iter<'a, (K, V)>>,
+}
+impl<'a, K, V> Iterator for HamtIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((k, v)) = self.collision.as_mut().and_then(|i| i.next()) {
+                return Some((k, v));
+            }
+            self.collision = None;
+            let top = self.stack.last_mut()?;
+            match top.next() {
+                Some(Entry::Leaf(_, k, v)) => return Some((k, v)),
+                Some(Entry::Collision(_, items)) => self.collision = Some(items.iter()),
+                Some(Entry::Node(node)) => self.stack.push(node.entries.iter()),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Hamt<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+impl<K: Hash + Eq + Clone, V: Clone> FromIterator<(K, V)> for Hamt<K, V> {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        iter.into_iter().fold(Hamt::new(), |map, (k, v)| map.insert(k, v))
+    }
+}
+
+#[test]
+fn list_test() {
+    let list: List = vec![Expr::Number(1), Expr::Number(2)].into();
+    let longer = list.cons(Expr::Number(0));
+    assert_eq!(3, longer.len());
+    assert_eq!(2, list.len());
+    // tail和原来的链表共享节点
+    assert!(Rc::ptr_eq(longer.tail().head.as_ref().unwrap(), list.head.as_ref().unwrap()));
+    // 很长的链表释放时不会栈溢出
+    let long: List = (0..200000).map(Expr::Number).collect();
+    drop(long);
+}
+#[test]
+fn vector_test() {
+    let v: TrieVector = (0..2000).map(Expr::Number).collect();
+    assert_eq!(2000, v.len());
+    assert!((0..2000).all(|i| matches!(v.get(i), Some(Expr::Number(n)) if *n as usize == i)));
+    let w = v.set(1500, Expr::Number(0)).unwrap();
+    assert!(matches!(w.get(1500), Some(Expr::Number(0))));
+    assert!(matches!(v.get(1500), Some(Expr::Number(1500))));
+    let s = v.slice(10, 20).unwrap();
+    assert!(matches!(s.get(0), Some(Expr::Number(10))));
+    assert!(s.get(10).is_none());
+    // 在截取的向量后面添加元素不影响原来的向量
+    let s = s.push(Expr::Number(99));
+    assert!(matches!(s.get(10), Some(Expr::Number(99))));
+    assert!(matches!(v.get(20), Some(Expr::Number(20))));
+    // 遍历截取的向量时跨过叶子的边界
+    let s = v.slice(30, 1000).unwrap();
+    assert!(s.iter().zip(30..1000).all(|(i, n)| matches!(i, Expr::Number(m) if *m == n)));
+    assert_eq!(970, s.iter().count());
+    assert_eq!(2000, v.iter().count());
+}
+#[test]
+fn hamt_test() {
+    let map: Hamt<u32, u32> = (0..5000).map(|i| (i, i * 2)).collect();
+    assert_eq!(5000, map.len());
+    assert!((0..5000).all(|i| map.get(&i) == Some(&(i * 2))));
+    let removed = (0..5000).step_by(2).fold(map.clone(), |m, i| m.remove(&i));
+    assert_eq!(2500, removed.len());
+    assert!(removed.get(&2).is_none() && removed.get(&3) == Some(&6));
+    assert_eq!(5000, map.iter().count());
+    assert_eq!(2500, removed.iter().count());
+}
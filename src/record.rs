@@ -0,0 +1,127 @@
+use std::rc::Rc;
+
+use crate::{
+    calc::{arg_type, args_count},
+    error::LispResult,
+    form::symbol,
+    token::{Expr, Native, TokensError},
+    varmap::EnvRef,
+};
+
+///`defrecord`定义的类型
+#[derive(Debug)]
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<String>,
+    ///每次`defrecord`都是新的类型，同名的类型重新定义之后和原来的类型不同
+    pub id: usize,
+}
+///记录的值，字段按定义的顺序保存
+#[derive(Debug)]
+pub struct Record {
+    pub ty: Rc<RecordType>,
+    pub values: Vec<Expr>,
+}
+
+// 在作用域中定义由闭包实现的函数，构造函数同时保存记录的类型
+fn define(
+    env: &EnvRef,
+    name: String,
+    record: Option<Rc<RecordType>>,
+    func: impl Fn(&[Expr]) -> LispResult<Expr> + 'static,
+) -> Expr {
+    let value = Expr::Native(Rc::new(Native { name: name.clone(), func: Box::new(func), record }));
+    env.def(&name, value.clone());
+    value
+}
+// 取出指定类型的记录
+fn extra_record<'a>(expr: &'a Expr, ty: &RecordType) -> LispResult<&'a Record> {
+    match expr {
+        Expr::Record(record) if record.ty.id == ty.id => Ok(record),
+        Expr::Record(record) => Err(TokensError::ArgType { expected: &ty.name, found: &record.ty.name, index: 1 }.into()),
+        _ => Err(arg_type(&ty.name, expr, 0)),
+    }
+}
+///`defrecord point {x y}`定义构造函数`point`、判断函数`point?`、
+///访问函数`point-x`和返回新记录的修改函数`point-with-x`
+pub fn defrecord(tokens: &[Expr], env: &EnvRef) -> LispResult<Expr> {
+    args_count(tokens, 2)?;
+    let name = symbol(&tokens[0])?.to_owned();
+    let fields = match &tokens[1] {
+        Expr::QExpr(fields) => fields
+            .iter()
+            .map(|i| symbol(i).map(|i| i.to_owned()))
+            .collect::<LispResult<Vec<String>>>()?,
+        _ => return Err(TokensError::InvalidExpression { expected: "qexpr", found: "expr" }.into()),
+    };
+    if fields.iter().enumerate().any(|(i, field)| fields[..i].contains(field)) {
+        return Err(TokensError::InvalidExpression { expected: "distinct fields", found: "duplicate field" }.into());
+    }
+    let ty = Rc::new(RecordType { name: name.clone(), fields, id: env.next_id() });
+    let t = ty.clone();
+    let constructor = define(env, name.clone(), Some(ty.clone()), move |values| {
+        args_count(values, t.fields.len())?;
+        Ok(Expr::Record(Rc::new(Record { ty: t.clone(), values: values.to_vec() })))
+    });
+    let t = ty.clone();
+    define(env, format!("{}?", name), None, move |values| {
+        args_count(values, 1)?;
+        Ok(Expr::Number(matches!(&values[0], Expr::Record(r) if r.ty.id == t.id) as u32))
+    });
+    for (index, field) in ty.fields.iter().enumerate() {
+        let t = ty.clone();
+        define(env, format!("{}-{}", name, field), None, move |values| {
+            args_count(values, 1)?;
+            Ok(extra_record(&values[0], &t)?.values[index].to_owned())
+        });
+        let t = ty.clone();
+        define(env, format!("{}-with-{}", name, field), None, move |values| {
+            args_count(values, 2)?;
+            let mut record = extra_record(&values[0], &t)?.values.to_owned();
+            record[index] = values[1].to_owned();
+            Ok(Expr::Record(Rc::new(Record { ty: t.clone(), values: record })))
+        });
+    }
+    Ok(constructor)
+}
+
+#[cfg(test)]
+fn run(s: &str, env: &EnvRef) -> LispResult<Expr> {
+    crate::token::read(s).and_then(|expr| expr.eval(env))
+}
+#[test]
+fn defrecord_test() {
+    let env = crate::varmap::Env::new();
+    run("defrecord point {x y}", &env).unwrap();
+    env.def("p", run("point 1 2", &env).unwrap());
+    assert_eq!("(point 1 2)", run("p", &env).unwrap().to_string());
+    assert!(matches!(run("point-y p", &env).unwrap(), Expr::Number(2)));
+    assert!(matches!(run("point? p", &env).unwrap(), Expr::Number(1)));
+    assert!(matches!(run("point? {1 2}", &env).unwrap(), Expr::Number(0)));
+    // 修改函数返回新的记录，原来的记录不变
+    assert_eq!("(point 5 2)", run("point-with-x p 5", &env).unwrap().to_string());
+    assert_eq!("(point 1 2)", run("p", &env).unwrap().to_string());
+    assert_eq!("arity-error", run("point 1", &env).unwrap_err().kind);
+    run("defrecord size {w h}", &env).unwrap();
+    let err = run("point-x (size 1 2)", &env).unwrap_err();
+    assert_eq!("expected point, got size at argument 1 of point-x", err.message);
+    // 重新定义之后是新的类型
+    run("defrecord point {x y}", &env).unwrap();
+    assert!(matches!(run("point? p", &env).unwrap(), Expr::Number(0)));
+}
+#[test]
+fn record_match_test() {
+    let env = crate::varmap::Env::new();
+    run("defrecord point {x y}", &env).unwrap();
+    let v = run("match (point 3 4) {(point 0 _) 0} {(point x y) (+ x y)}", &env).unwrap();
+    assert!(matches!(v, Expr::Number(7)));
+    let v = run("get (hash-map (point 1 2) 9) (point 1 2)", &env).unwrap();
+    assert!(matches!(v, Expr::Number(9)));
+    // 按类型匹配，重新定义的同名类型不会匹配原来的记录
+    env.def("p", run("point 1 2", &env).unwrap());
+    run("defrecord point {x y}", &env).unwrap();
+    let v = run("match p {(point x y) 1} {_ 0}", &env).unwrap();
+    assert!(matches!(v, Expr::Number(0)));
+    let v = run("match (point 1 2) {(point x y) 1} {_ 0}", &env).unwrap();
+    assert!(matches!(v, Expr::Number(1)));
+}
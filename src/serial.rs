@@ -0,0 +1,221 @@
+//! 自动机和变量表保存到文件的二进制格式，所有整数都是小端序
+//!
+//! 头部20字节：魔数(4) 版本(2) 保留(2) 内容长度(4) 内容的校验和(8)，后面是内容
+
+use thiserror::Error;
+
+use crate::{
+    map::{Key, Map, entries},
+    persistent::TrieVector,
+    token::Expr,
+};
+
+///格式的版本，内容的布局改变时增加
+pub const VERSION: u16 = 1;
+const HEADER_LEN: usize = 20;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SerialError {
+    #[error("Invalid magic number, expected {expected:?}")]
+    Magic { expected: [u8; 4] },
+    #[error("Unsupported format version {found}, expected {expected}")]
+    Version { expected: u16, found: u16 },
+    #[error("Checksum mismatch")]
+    Checksum,
+    #[error("Unexpected end of data")]
+    Truncated,
+    #[error("Corrupted data: {0}")]
+    Corrupted(&'static str),
+    #[error("Can not serialize value of type {0}")]
+    Unsupported(&'static str),
+}
+
+///FNV-1a校验和，用于发现文件损坏，不能防止有意的修改
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+///在内容前面加上头部
+pub fn seal(magic: [u8; 4], payload: Vec<u8>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(&magic);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&0u16.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&checksum(&payload).to_le_bytes());
+    bytes.extend(payload);
+    bytes
+}
+///检查头部和校验和，返回内容，不复制数据
+pub fn open(magic: [u8; 4], bytes: &[u8]) -> Result<&[u8], SerialError> {
+    let mut header = Reader::new(bytes);
+    if header.slice(4)? != magic {
+        return Err(SerialError::Magic { expected: magic });
+    }
+    // 先检查版本，新版本的文件报告版本错误而不是校验和错误
+    let version = u16::from_le_bytes([header.u8()?, header.u8()?]);
+    if version != VERSION {
+        return Err(SerialError::Version { expected: VERSION, found: version });
+    }
+    header.slice(2)?;
+    let len = header.u32()? as usize;
+    let sum = u64::from_le_bytes(header.slice(8)?.try_into().expect("长度是8"));
+    let payload = header.slice(len)?;
+    if checksum(payload) != sum {
+        return Err(SerialError::Checksum);
+    }
+    Ok(payload)
+}
+///按顺序读取，数据不够时返回`Truncated`
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+    pub fn slice(&mut self, len: usize) -> Result<&'a [u8], SerialError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or(SerialError::Truncated)?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+    pub fn u8(&mut self) -> Result<u8, SerialError> {
+        Ok(self.slice(1)?[0])
+    }
+    pub fn u32(&mut self) -> Result<u32, SerialError> {
+        Ok(u32_at(self.slice(4)?, 0))
+    }
+    fn str(&mut self) -> Result<&'a str, SerialError> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.slice(len)?).map_err(|_| SerialError::Corrupted("invalid utf-8"))
+    }
+}
+///读取第index个u32，调用之前已经检查过长度
+pub fn u32_at(bytes: &[u8], index: usize) -> u32 {
+    u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().expect("长度是4"))
+}
+pub fn put_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+// 值的类型标记
+const NUMBER: u8 = 0;
+const SYMBOL: u8 = 1;
+const STR: u8 = 2;
+const CHAR: u8 = 3;
+const QEXPR: u8 = 4;
+const VECTOR: u8 = 5;
+const MAP: u8 = 6;
+// 读取时允许的最大嵌套层数，防止构造的数据让递归耗尽栈空间
+const MAX_NESTING: usize = 256;
+
+///保存数据类型的值，函数等运行时的值不能保存
+pub fn write_expr(bytes: &mut Vec<u8>, expr: &Expr) -> Result<(), SerialError> {
+    let put_str = |bytes: &mut Vec<u8>, tag: u8, s: &str| {
+        bytes.push(tag);
+        put_u32(bytes, s.len() as u32);
+        bytes.extend_from_slice(s.as_bytes());
+    };
+    match expr {
+        Expr::Number(v) => {
+            bytes.push(NUMBER);
+            put_u32(bytes, *v);
+        }
+        Expr::Symbol(s) => put_str(bytes, SYMBOL, s),
+        Expr::Str(s) => put_str(bytes, STR, s),
+        Expr::Char(c) => {
+            bytes.push(CHAR);
+            put_u32(bytes, *c as u32);
+        }
+        Expr::QExpr(items) => {
+            bytes.push(QEXPR);
+            put_u32(bytes, items.len() as u32);
+            items.iter().try_for_each(|i| write_expr(bytes, i))?;
+        }
+        Expr::Vector(items) => {
+            bytes.push(VECTOR);
+            put_u32(bytes, items.len() as u32);
+            items.iter().try_for_each(|i| write_expr(bytes, i))?;
+        }
+        Expr::Map(map) => {
+            bytes.push(MAP);
+            put_u32(bytes, map.len() as u32);
+            for (k, v) in entries(map) {
+                write_expr(bytes, k.expr())?;
+                write_expr(bytes, v)?;
+            }
+        }
+        _ => return Err(SerialError::Unsupported(expr.type_name())),
+    }
+    Ok(())
+}
+///读取`write_expr`保存的值，嵌套超过`MAX_NESTING`层时返回`Corrupted`
+pub fn read_expr(reader: &mut Reader) -> Result<Expr, SerialError> {
+    read_nested(reader, 0)
+}
+fn read_nested(reader: &mut Reader, depth: usize) -> Result<Expr, SerialError> {
+    if depth > MAX_NESTING {
+        return Err(SerialError::Corrupted("nesting too deep"));
+    }
+    let read_expr = |reader: &mut Reader| read_nested(reader, depth + 1);
+    Ok(match reader.u8()? {
+        NUMBER => Expr::Number(reader.u32()?),
+        SYMBOL => Expr::Symbol(reader.str()?.to_string()),
+        STR => Expr::Str(reader.str()?.into()),
+        CHAR => Expr::Char(char::from_u32(reader.u32()?).ok_or(SerialError::Corrupted("invalid char"))?),
+        QEXPR => {
+            let len = reader.u32()?;
+            Expr::QExpr((0..len).map(|_| read_expr(reader)).collect::<Result<Vec<_>, _>>()?.into())
+        }
+        VECTOR => {
+            let len = reader.u32()?;
+            Expr::Vector((0..len).map(|_| read_expr(reader)).collect::<Result<TrieVector, _>>()?)
+        }
+        MAP => {
+            let mut map = Map::new();
+            for _ in 0..reader.u32()? {
+                let key = read_expr(reader)?;
+                map = map.insert(Key::new(&key), read_expr(reader)?);
+            }
+            Expr::Map(map)
+        }
+        _ => return Err(SerialError::Corrupted("unknown value tag")),
+    })
+}
+
+#[cfg(test)]
+fn run(s: &str) -> Expr {
+    crate::token::read(s).and_then(|expr| expr.eval(&crate::varmap::Env::new())).unwrap()
+}
+#[test]
+fn expr_test() {
+    let value = run("list 1 `a \"中文\" #\\x {2 [3 #{k {}}]}");
+    let mut bytes = Vec::new();
+    write_expr(&mut bytes, &value).unwrap();
+    assert_eq!(value, read_expr(&mut Reader::new(&bytes)).unwrap());
+    assert_eq!(Err(SerialError::Truncated), read_expr(&mut Reader::new(&bytes[..bytes.len() - 1])));
+    let mut bytes = Vec::new();
+    let err = write_expr(&mut bytes, &Expr::QExpr(crate::persistent::List::new().cons(run("\\ {x} {x}")))).unwrap_err();
+    assert_eq!(SerialError::Unsupported("function"), err);
+    // 嵌套太深的数据返回错误，不会栈溢出
+    let nested: Vec<u8> = std::iter::repeat_n([QEXPR, 1, 0, 0, 0], 100_000).flatten().collect();
+    assert_eq!(Err(SerialError::Corrupted("nesting too deep")), read_expr(&mut Reader::new(&nested)));
+    let mut bytes = Vec::new();
+    let value = (0..MAX_NESTING).fold(Expr::Number(1), |value, _| Expr::QExpr(vec![value].into()));
+    write_expr(&mut bytes, &value).unwrap();
+    assert_eq!(value, read_expr(&mut Reader::new(&bytes)).unwrap());
+}
+#[test]
+fn header_test() {
+    let bytes = seal(*b"TEST", vec![1, 2, 3]);
+    assert_eq!(&[1, 2, 3], open(*b"TEST", &bytes).unwrap());
+    assert_eq!(Err(SerialError::Magic { expected: *b"ACM0" }), open(*b"ACM0", &bytes));
+    let mut corrupted = bytes.clone();
+    corrupted[HEADER_LEN] = 9;
+    assert_eq!(Err(SerialError::Checksum), open(*b"TEST", &corrupted));
+    let mut newer = bytes.clone();
+    newer[4] = 2;
+    assert_eq!(Err(SerialError::Version { expected: 1, found: 2 }), open(*b"TEST", &newer));
+    assert_eq!(Err(SerialError::Truncated), open(*b"TEST", &bytes[..bytes.len() - 1]));
+}
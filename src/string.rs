@@ -0,0 +1,257 @@
+use std::rc::Rc;
+
+use crate::{
+    calc::{arg_type, args_count, extra_num},
+    error::LispResult,
+    form::min_args,
+    persistent::List,
+    token::{Expr, Num, TokensError},
+};
+
+///取出字符串，index是参数的位置，从0开始
+pub fn extra_str(expr: &Expr, index: usize) -> LispResult<&str> {
+    match expr {
+        Expr::Str(s) => Ok(s),
+        _ => Err(arg_type("string", expr, index)),
+    }
+}
+fn extra_char(expr: &Expr, index: usize) -> LispResult<char> {
+    match expr {
+        Expr::Char(c) => Ok(*c),
+        _ => Err(arg_type("char", expr, index)),
+    }
+}
+fn string(s: impl Into<Rc<str>>) -> Expr {
+    Expr::Str(s.into())
+}
+// 字节位置转换为字符位置
+fn char_index(s: &str, byte: usize) -> usize {
+    s[..byte].chars().count()
+}
+// 字符位置转换为字节位置，可以等于字符个数
+fn byte_index(s: &str, index: usize) -> LispResult<usize> {
+    s.char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(s.len()))
+        .nth(index)
+        .ok_or_else(|| TokensError::IndexOutOfBounds { index, len: s.chars().count() }.into())
+}
+fn radix(tokens: &[Expr], index: usize) -> LispResult<u32> {
+    match tokens.get(index) {
+        Some(radix) => match extra_num(radix, index)? {
+            radix @ 2..=36 => Ok(radix),
+            _ => Err(TokensError::InvalidExpression { expected: "radix between 2 and 36", found: "number" }.into()),
+        },
+        None => Ok(10),
+    }
+}
+///字符个数，不是字节数
+pub fn str_len(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::Number(extra_str(&tokens[0], 0)?.chars().count() as Num))
+}
+///`concat "a" #\b`，字符串和字符连接成新的字符串
+pub fn concat(tokens: &[Expr]) -> LispResult<Expr> {
+    let mut s = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Expr::Char(c) => s.push(*c),
+            token => s.push_str(extra_str(token, i)?),
+        }
+    }
+    Ok(string(s))
+}
+///`substr s start end`，按字符截取，不包含end，省略end时取到末尾
+pub fn substr(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 2)?;
+    let s = extra_str(&tokens[0], 0)?;
+    let start = byte_index(s, extra_num(&tokens[1], 1)? as usize)?;
+    let end = match tokens.get(2) {
+        Some(end) => byte_index(s, extra_num(end, 2)? as usize)?,
+        None => s.len(),
+    };
+    if start > end {
+        return Err(TokensError::IndexOutOfBounds { index: char_index(s, start), len: s.chars().count() }.into());
+    }
+    Ok(string(&s[start..end]))
+}
+///`split "a,b" ","`，分隔符为空时拆分成单个字符
+pub fn split(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 2)?;
+    let s = extra_str(&tokens[0], 0)?;
+    let sep = extra_str(&tokens[1], 1)?;
+    let parts: List = if sep.is_empty() {
+        s.chars().map(|c| string(c.to_string())).collect()
+    } else {
+        s.split(sep).map(string).collect()
+    };
+    Ok(Expr::QExpr(parts))
+}
+///`join {"a" "b"} ","`，省略分隔符时直接连接
+pub fn join(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 1)?;
+    let Expr::QExpr(items) = &tokens[0] else {
+        return Err(TokensError::InvalidExpression { expected: "qexpr", found: "expr" }.into());
+    };
+    let sep = match tokens.get(1) {
+        Some(sep) => extra_str(sep, 1)?,
+        None => "",
+    };
+    // 列表中的元素属于第一个参数
+    let parts = items.iter().map(|i| extra_str(i, 0)).collect::<LispResult<Vec<_>>>()?;
+    Ok(string(parts.join(sep)))
+}
+pub fn trim(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(string(extra_str(&tokens[0], 0)?.trim()))
+}
+pub fn upper(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(string(extra_str(&tokens[0], 0)?.to_uppercase()))
+}
+pub fn lower(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(string(extra_str(&tokens[0], 0)?.to_lowercase()))
+}
+///`replace s from to`，替换所有出现的位置
+pub fn replace(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 3)?;
+    let from = extra_str(&tokens[1], 1)?;
+    if from.is_empty() {
+        return Err(TokensError::InvalidExpression { expected: "non-empty pattern", found: "\"\"" }.into());
+    }
+    Ok(string(extra_str(&tokens[0], 0)?.replace(from, extra_str(&tokens[2], 2)?)))
+}
+pub fn starts_with(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 2)?;
+    Ok(Expr::Number(extra_str(&tokens[0], 0)?.starts_with(extra_str(&tokens[1], 1)?) as Num))
+}
+pub fn str_contains(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 2)?;
+    Ok(Expr::Number(extra_str(&tokens[0], 0)?.contains(extra_str(&tokens[1], 1)?) as Num))
+}
+///第一次出现的字符位置，没有找到时返回`{}`
+pub fn index_of(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 2)?;
+    let s = extra_str(&tokens[0], 0)?;
+    Ok(match s.find(extra_str(&tokens[1], 1)?) {
+        Some(i) => Expr::Number(char_index(s, i) as Num),
+        None => Expr::QExpr(List::new()),
+    })
+}
+///`str->num "ff" 16`，默认是十进制
+pub fn str_to_num(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 1)?;
+    let s = extra_str(&tokens[0], 0)?;
+    Num::from_str_radix(s.trim(), radix(tokens, 1)?)
+        .map(Expr::Number)
+        .map_err(|_| TokensError::InvalidExpression { expected: "number", found: "string" }.into())
+}
+///`num->str 255 16`，大于10的数字使用小写字母
+pub fn num_to_str(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 1)?;
+    let mut n = extra_num(&tokens[0], 0)?;
+    let radix = radix(tokens, 1)?;
+    let mut digits = Vec::new();
+    loop {
+        digits.push(char::from_digit(n % radix, radix).unwrap_or('?'));
+        n /= radix;
+        if n == 0 {
+            break;
+        }
+    }
+    Ok(string(digits.iter().rev().collect::<String>()))
+}
+///把字符串拆分成字符
+pub fn chars(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::QExpr(extra_str(&tokens[0], 0)?.chars().map(Expr::Char).collect()))
+}
+///字符的Unicode码点
+pub fn char_to_int(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::Number(extra_char(&tokens[0], 0)? as Num))
+}
+pub fn int_to_char(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    char::from_u32(extra_num(&tokens[0], 0)?)
+        .map(Expr::Char)
+        .ok_or_else(|| TokensError::InvalidExpression { expected: "unicode scalar value", found: "number" }.into())
+}
+pub fn char_alphabetic(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::Number(extra_char(&tokens[0], 0)?.is_alphabetic() as Num))
+}
+// 大小写转换的结果不是一个字符时(例如`ß`)保持不变
+fn map_char<I: Iterator<Item = char>>(c: char, f: fn(char) -> I) -> char {
+    let mut mapped = f(c);
+    match (mapped.next(), mapped.next()) {
+        (Some(v), None) => v,
+        _ => c,
+    }
+}
+pub fn char_upper(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::Char(map_char(extra_char(&tokens[0], 0)?, char::to_uppercase)))
+}
+pub fn char_lower(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::Char(map_char(extra_char(&tokens[0], 0)?, char::to_lowercase)))
+}
+
+#[cfg(test)]
+fn try_run(s: &str) -> LispResult<Expr> {
+    crate::token::read(s).and_then(|expr| expr.eval(&crate::varmap::Env::new()))
+}
+#[cfg(test)]
+fn run(s: &str) -> Expr {
+    try_run(s).unwrap()
+}
+#[test]
+fn unicode_test() {
+    assert!(matches!(run("str-len \"中文abc\""), Expr::Number(5)));
+    assert_eq!("\"文a\"", run("substr \"中文abc\" 1 3").to_string());
+    assert!(matches!(run("index-of \"中文abc\" \"b\""), Expr::Number(3)));
+    assert_eq!("{ }", run("index-of \"abc\" \"x\"").to_string());
+    assert_eq!("{ \"中\" \"文\" }", run("split \"中文\" \"\"").to_string());
+    assert_eq!("\"STRASSE\"", run("upper \"straße\"").to_string());
+    let err = try_run("substr \"中文\" 3").unwrap_err();
+    assert_eq!("index-error", err.kind);
+    // 开始位置在结束位置之后时报告字符串的长度
+    let err = try_run("substr \"中文abc\" 2 1").unwrap_err();
+    assert_eq!("Index 2 out of bounds for length 5", err.message);
+}
+#[test]
+fn string_test() {
+    assert_eq!("\"a-b-c\"", run("join (split \"a,b,c\" \",\") \"-\"").to_string());
+    assert_eq!("\"ab\"", run("concat \"a\" \"b\"").to_string());
+    assert_eq!("\"x y\"", run("trim \"  x y \\n\"").to_string());
+    assert_eq!("\"a\\\"b\"", run("replace \"a'b\" \"'\" \"\\\"\"").to_string());
+    assert!(matches!(run("starts-with? \"hello\" \"he\""), Expr::Number(1)));
+    assert!(matches!(run("contains? \"hello\" \"ll\""), Expr::Number(1)));
+    assert!(matches!(run("str->num \"ff\" 16"), Expr::Number(255)));
+    assert_eq!("\"11111111\"", run("num->str 255 2").to_string());
+    assert_eq!("\"0\"", run("num->str 0").to_string());
+}
+#[test]
+fn char_test() {
+    assert_eq!("{ #\\a #\\space #\\newline #\\中 #\\( }", run("list #\\a #\\space #\\newline #\\x4E2D #\\(").to_string());
+    assert!(matches!(run("char->int #\\x4E2D"), Expr::Number(0x4E2D)));
+    assert!(matches!(run("int->char 97"), Expr::Char('a')));
+    assert!(matches!(run("char-alphabetic? #\\中"), Expr::Number(1)));
+    assert!(matches!(run("char-alphabetic? #\\1"), Expr::Number(0)));
+    assert!(matches!(run("char-upper #\\a"), Expr::Char('A')));
+    assert!(matches!(run("char-upper #\\ß"), Expr::Char('ß')));
+    assert_eq!("{ #\\中 #\\文 }", run("chars \"中文\"").to_string());
+    // 按字符遍历字符串
+    let v = run("let {{s \"\"}} (for c \"abc\" (set s (concat (char-upper c) s))) s");
+    assert_eq!("\"CBA\"", v.to_string());
+}
+#[test]
+fn type_error_test() {
+    let message = |s: &str| try_run(s).unwrap_err().message;
+    assert_eq!("expected number, got string at argument 2 of substr", message("substr \"abc\" \"x\""));
+    assert_eq!("expected string, got number at argument 2 of concat", message("concat \"a\" 1"));
+    assert_eq!("expected char, got string at argument 1 of char->int", message("char->int \"a\""));
+    assert_eq!("expected string, got number at argument 1 of join", message("join {\"a\" 1}"));
+}
@@ -0,0 +1,322 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    error::LispResult,
+    form::{min_args, symbol},
+    token::{Expr, TokensError},
+    varmap::EnvRef,
+};
+
+const ELLIPSIS: &str = "...";
+
+///`syntax-rules`风格的宏，由多条模式和模板组成
+#[derive(Debug)]
+pub struct SyntaxRules {
+    // 需要原样匹配的符号
+    literals: Vec<String>,
+    // 模式和对应的模板
+    rules: Vec<(Expr, Expr)>,
+}
+// 模式变量匹配到的值，在`...`中匹配到的是多个值
+#[derive(Debug, Clone)]
+enum Binding {
+    One(Expr),
+    Many(Vec<Binding>),
+}
+type Bindings = HashMap<String, Binding>;
+
+// 把qexpr和表达式都看作列表，`(+ 1 2)`看作`{+ 1 2}`
+fn as_list(expr: &Expr) -> Option<Vec<Expr>> {
+    match expr {
+        Expr::QExpr(items) => Some(items.to_vec()),
+        Expr::Operator(op, args, _) => {
+            let mut items = vec![Expr::Symbol(op.to_owned())];
+            items.extend(args.iter().cloned());
+            Some(items)
+        }
+        _ => None,
+    }
+}
+// 把列表还原成和模板相同的形式
+fn from_list(template: &Expr, items: Vec<Expr>) -> Expr {
+    match (template, items.as_slice()) {
+        (Expr::Operator(_, _, span), [Expr::Symbol(op), args @ ..]) => {
+            Expr::Operator(op.to_owned(), args.to_vec(), *span)
+        }
+        _ => Expr::QExpr(items.into()),
+    }
+}
+fn is_ellipsis(expr: Option<&Expr>) -> bool {
+    matches!(expr, Some(Expr::Symbol(v)) if v == ELLIPSIS)
+}
+
+impl SyntaxRules {
+    ///匹配宏调用并展开模板，没有匹配的规则时报错，重命名的编号由`env`的全局作用域分配
+    pub fn expand(&self, name: &str, args: &[Expr], env: &EnvRef) -> LispResult<Expr> {
+        for (pattern, template) in self.rules.iter() {
+            let pattern = as_list(pattern).unwrap_or_default();
+            let mut bindings = Bindings::new();
+            // 模式的第一个元素是宏的名字，不参与匹配
+            if self.match_list(&pattern[1..], args, &mut bindings) {
+                let template = rename(template, &bindings, env);
+                return instantiate(&template, &bindings)?.to_code();
+            }
+        }
+        Err(TokensError::InvalidExpression { expected: "matching syntax rule", found: name }.into())
+    }
+    fn match_one(&self, pattern: &Expr, input: &Expr, bindings: &mut Bindings) -> bool {
+        match pattern {
+            Expr::Symbol(v) if v == "_" => true,
+            Expr::Symbol(v) if self.literals.contains(v) => matches!(input, Expr::Symbol(i) if i == v),
+            Expr::Symbol(v) => {
+                bindings.insert(v.to_owned(), Binding::One(input.to_owned()));
+                true
+            }
+            Expr::Number(v) => matches!(input, Expr::Number(i) if i == v),
+            Expr::QExpr(_) | Expr::Operator(..) => match (as_list(pattern), as_list(input)) {
+                (Some(pattern), Some(input)) => self.match_list(&pattern, &input, bindings),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+    // 匹配列表，列表中最多有一个`...`，`...`前面的模式匹配任意多个元素
+    fn match_list(&self, pattern: &[Expr], input: &[Expr], bindings: &mut Bindings) -> bool {
+        let ellipsis = (0..pattern.len()).find(|i| is_ellipsis(pattern.get(i + 1)));
+        let Some(index) = ellipsis else {
+            return pattern.len() == input.len()
+                && pattern.iter().zip(input).all(|(p, i)| self.match_one(p, i, bindings));
+        };
+        let after = &pattern[index + 2..];
+        if input.len() < index + after.len() {
+            return false;
+        }
+        let rest_start = input.len() - after.len();
+        let prefix = pattern[..index].iter().zip(&input[..index]);
+        let suffix = after.iter().zip(&input[rest_start..]);
+        if !prefix.chain(suffix).all(|(p, i)| self.match_one(p, i, bindings)) {
+            return false;
+        }
+        // 每个元素单独匹配，再把同一个变量的值收集起来
+        let sub_pattern = &pattern[index];
+        let mut matched = Vec::new();
+        for item in &input[index..rest_start] {
+            let mut sub = Bindings::new();
+            if !self.match_one(sub_pattern, item, &mut sub) {
+                return false;
+            }
+            matched.push(sub);
+        }
+        // `defsyntax`已经检查过重复的变量，匹配成功时每个变量都有值
+        for var in pattern_vars(sub_pattern, &self.literals) {
+            let values = matched.iter_mut().map(|sub| sub.remove(&var).expect("pattern var")).collect();
+            bindings.insert(var, Binding::Many(values));
+        }
+        true
+    }
+}
+// 模式中出现的变量
+fn pattern_vars(pattern: &Expr, literals: &[String]) -> Vec<String> {
+    match pattern {
+        Expr::Symbol(v) if v == "_" || v == ELLIPSIS || literals.contains(v) => vec![],
+        Expr::Symbol(v) => vec![v.to_owned()],
+        Expr::QExpr(_) | Expr::Operator(..) => as_list(pattern)
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|i| pattern_vars(i, literals))
+            .collect(),
+        _ => vec![],
+    }
+}
+// 同一个模式中的变量不能重复，否则`...`收集值时会少一个
+fn check_pattern(pattern: &Expr, literals: &[String]) -> LispResult<()> {
+    let items = as_list(pattern).unwrap_or_default();
+    let vars: Vec<String> = items.iter().skip(1).flat_map(|i| pattern_vars(i, literals)).collect();
+    match vars.iter().enumerate().find(|(i, var)| vars[..*i].contains(var)) {
+        Some((_, var)) => Err(TokensError::InvalidExpression { expected: "distinct pattern variables", found: var }.into()),
+        None => Ok(()),
+    }
+}
+// 用匹配到的值替换模板中的模式变量
+fn instantiate(template: &Expr, bindings: &Bindings) -> LispResult<Expr> {
+    match template {
+        Expr::Symbol(v) => match bindings.get(v) {
+            Some(Binding::One(value)) => Ok(value.to_owned()),
+            Some(Binding::Many(_)) => Err(TokensError::InvalidExpression { expected: "...", found: v }.into()),
+            None => Ok(template.to_owned()),
+        },
+        Expr::QExpr(_) | Expr::Operator(..) => {
+            let items = as_list(template).unwrap_or_default();
+            let mut result = Vec::new();
+            let mut index = 0;
+            while index < items.len() {
+                let item = &items[index];
+                if !is_ellipsis(items.get(index + 1)) {
+                    result.push(instantiate(item, bindings)?);
+                    index += 1;
+                    continue;
+                }
+                // `x ...`按照匹配到的次数重复展开
+                let vars: Vec<(&String, &Vec<Binding>)> = template_vars(item)
+                    .into_iter()
+                    .filter_map(|var| match bindings.get_key_value(&var) {
+                        Some((name, Binding::Many(values))) => Some((name, values)),
+                        _ => None,
+                    })
+                    .collect();
+                let len = vars.first().map(|(_, values)| values.len()).unwrap_or(0);
+                for i in 0..len {
+                    let mut sub = bindings.clone();
+                    for (name, values) in vars.iter() {
+                        if let Some(value) = values.get(i) {
+                            sub.insert(name.to_string(), value.to_owned());
+                        }
+                    }
+                    result.push(instantiate(item, &sub)?);
+                }
+                index += 2;
+            }
+            Ok(from_list(template, result))
+        }
+        _ => Ok(template.to_owned()),
+    }
+}
+fn template_vars(template: &Expr) -> Vec<String> {
+    pattern_vars(template, &[])
+}
+// 收集模板中新引入的绑定，包括let的变量、函数参数和for的循环变量
+fn introduced(template: &Expr, bindings: &Bindings, names: &mut Vec<String>) {
+    let Some(items) = as_list(template) else {
+        return;
+    };
+    let mut push = |expr: &Expr| {
+        if let Expr::Symbol(v) = expr
+            && !bindings.contains_key(v)
+            && v != "&"
+            && v != ELLIPSIS
+            && !names.contains(v)
+        {
+            names.push(v.to_owned());
+        }
+    };
+    match items.as_slice() {
+        [Expr::Symbol(op), Expr::QExpr(pairs), ..] if op == "let" || op == "let*" || op == "letrec" => {
+            for pair in pairs {
+                if let Expr::QExpr(pair) = pair
+                    && let Some(name) = pair.first()
+                {
+                    push(name);
+                }
+            }
+        }
+        [Expr::Symbol(op), Expr::QExpr(params), ..] if op == "\\" => params.iter().for_each(push),
+        [Expr::Symbol(op), name, ..] if op == "for" => push(name),
+        _ => {}
+    }
+    for i in items.iter() {
+        introduced(i, bindings, names);
+    }
+}
+// 重命名模板中引入的绑定，避免捕获宏调用处的同名变量。
+// `#`不能被词法分析读取，所以重命名后的变量不会和用户的变量冲突
+fn rename(template: &Expr, bindings: &Bindings, env: &EnvRef) -> Expr {
+    let mut names = Vec::new();
+    introduced(template, bindings, &mut names);
+    let renamed: HashMap<String, String> = names
+        .into_iter()
+        .map(|name| {
+            let id = env.next_id();
+            let value = format!("{}#{}", name, id);
+            (name, value)
+        })
+        .collect();
+    replace_symbols(template, &renamed)
+}
+fn replace_symbols(template: &Expr, renamed: &HashMap<String, String>) -> Expr {
+    match template {
+        Expr::Symbol(v) => Expr::Symbol(renamed.get(v).unwrap_or(v).to_owned()),
+        Expr::QExpr(_) | Expr::Operator(..) => {
+            let items = as_list(template).unwrap_or_default();
+            let items = items.iter().map(|i| replace_symbols(i, renamed)).collect();
+            from_list(template, items)
+        }
+        _ => template.to_owned(),
+    }
+}
+
+///定义宏 `defsyntax my-or {} {{_} 0} {{_ e} e} {{_ e r ...} {let {{t e}} (if t t (my-or r ...))}}`
+pub fn defsyntax(tokens: &[Expr], env: &EnvRef) -> LispResult<Expr> {
+    min_args(tokens, 2)?;
+    let name = symbol(&tokens[0])?;
+    let literals = match &tokens[1] {
+        Expr::QExpr(items) => items
+            .iter()
+            .map(|i| symbol(i).map(|i| i.to_owned()))
+            .collect::<LispResult<Vec<String>>>()?,
+        _ => return Err(TokensError::InvalidExpression { expected: "qexpr", found: "expr" }.into()),
+    };
+    let rules = tokens[2..]
+        .iter()
+        .map(|rule| match rule {
+            Expr::QExpr(rule) => match rule.to_vec().as_slice() {
+                [pattern @ Expr::QExpr(p), template] if !p.is_empty() => Ok((pattern.to_owned(), template.to_owned())),
+                _ => Err(TokensError::InvalidExpression { expected: "{pattern template}", found: "qexpr" }.into()),
+            },
+            _ => Err(TokensError::InvalidExpression { expected: "{pattern template}", found: "expr" }.into()),
+        })
+        .collect::<LispResult<Vec<(Expr, Expr)>>>()?;
+    rules.iter().try_for_each(|(pattern, _)| check_pattern(pattern, &literals))?;
+    let value = Expr::Syntax(Rc::new(SyntaxRules { literals, rules }));
+    env.def(name, value.clone());
+    Ok(value)
+}
+
+#[cfg(test)]
+fn try_run(s: &str, env: &EnvRef) -> LispResult<Expr> {
+    crate::token::read(s).and_then(|expr| crate::macros::expand(&expr, env)).and_then(|expr| expr.eval(env))
+}
+#[cfg(test)]
+fn run(s: &str, env: &EnvRef) -> Expr {
+    try_run(s, env).unwrap()
+}
+#[test]
+fn ellipsis_test() {
+    let env = crate::varmap::Env::new();
+    run("defsyntax sum {} {{_ x ...} {+ 0 x ...}}", &env);
+    assert!(matches!(run("sum 1 2 3", &env), Expr::Number(6)));
+    assert!(matches!(run("sum", &env), Expr::Number(0)));
+    run("defsyntax pairs {} {{_ {k v} ...} {+ (* k v) ...}}", &env);
+    assert!(matches!(run("pairs {1 2} {3 4}", &env), Expr::Number(14)));
+}
+#[test]
+fn duplicate_var_test() {
+    let env = crate::varmap::Env::new();
+    let err = try_run("defsyntax dup {} {{_ {a a} ...} {+ a ...}}", &env).unwrap_err();
+    assert_eq!("invalid-expression", err.kind);
+    assert!(env.get("dup").is_none());
+    assert!(try_run("defsyntax dup2 {} {{_ a b a} a}", &env).is_err());
+    // 不同规则中的变量可以同名
+    run("defsyntax two {} {{_ a} a} {{_ a b} b}", &env);
+    assert!(matches!(run("two 1 2", &env), Expr::Number(2)));
+}
+#[test]
+fn literal_test() {
+    let env = crate::varmap::Env::new();
+    run("defsyntax pick {then else} {{_ then a b} a} {{_ else a b} b}", &env);
+    assert!(matches!(run("pick then 1 2", &env), Expr::Number(1)));
+    assert!(matches!(run("pick else 1 2", &env), Expr::Number(2)));
+}
+#[test]
+fn hygiene_test() {
+    let env = crate::varmap::Env::new();
+    run(
+        "defsyntax my-or {} {{_} 0} {{_ e} e} {{_ e r ...} {let {{t e}} (if t t (my-or r ...))}}",
+        &env,
+    );
+    assert!(matches!(run("my-or 0 0 3", &env), Expr::Number(3)));
+    // 模板中的t被重命名，不会捕获调用处的t
+    assert!(matches!(run("let {{t 5}} (my-or 0 t)", &env), Expr::Number(5)));
+    run("defsyntax swap! {} {{_ a b} {let {{tmp a}} (set a b) (set b tmp)}}", &env);
+    let v = run("let {{tmp 1} {y 2}} (swap! tmp y) (- (* tmp 10) y)", &env);
+    assert!(matches!(v, Expr::Number(19)));
+}
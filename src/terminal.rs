@@ -1,13 +1,20 @@
 use std::io::Write;
 
-use crate::token::Tokens;
+use crate::{
+    macros::expand,
+    token::read,
+    varmap::{Env, EnvRef},
+};
 
-pub struct Terminal {}
+pub struct Terminal {
+    // 全局作用域，在多次输入之间保留
+    env: EnvRef,
+}
 
 impl Terminal {
     // 创面一个Terminal管理器
     pub fn new() -> Self {
-        Self {}
+        Self { env: Env::new() }
     }
     pub fn print<S: AsRef<str>>(s: S) {
         print!("lispy>{}", s.as_ref());
@@ -24,9 +31,12 @@ impl Terminal {
                 .read_line(&mut input)
                 .expect("Failed read user input");
             let ss = input.trim();
-            let mut tokens = Tokens::new(ss.to_string());
-            let expr = tokens.parser();
-            Terminal::println(format!("calc result is '{}'", expr.calc()));
+            // 先展开宏再计算，读取时的语法错误和计算时的错误一样输出
+            let result = read(ss).and_then(|expr| expand(&expr, &self.env)).and_then(|expr| expr.eval(&self.env));
+            match result {
+                Ok(v) => Terminal::println(format!("calc result is '{}'", v)),
+                Err(err) => Terminal::println(format!("error: {}", err)),
+            }
         }
     }
 }
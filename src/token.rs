@@ -1,16 +1,90 @@
-use std::{collections::VecDeque, fmt, iter::Peekable};
+use std::{collections::VecDeque, fmt, iter::Peekable, rc::Rc};
 
 use thiserror::Error;
 
- use crate::calc::{add, div, head, max, mi, min, mmod, mul, sub};
+use crate::{
+    calc::{add, cons, div, eq, ge, gt, head, le, list, lt, max, mi, min, mmod, mul, ne, range, sub, tail},
+    equal::{is_eq, is_equal},
+    error::{LispError, LispResult, error_kind, error_message, error_span, error_value, throw},
+    eval::{Continuation, Generator, eval},
+    persistent::{List, TrieVector},
+    map::{Map, assoc, contains, dissoc, entries, from_pairs, get, hash_map, keys, merge, vals},
+    vector::{nth, subvec, vector, vlen, vpush, vset},
+    string::{
+        char_alphabetic, char_lower, char_to_int, char_upper, chars, concat, index_of, int_to_char, join, lower,
+        num_to_str, replace, split, starts_with, str_len, str_to_num, substr, trim, upper,
+    },
+    record::{Record, RecordType},
+    syntax::SyntaxRules,
+    types::{
+        is_char, is_error, is_fn, is_generator, is_list, is_map, is_number, is_record, is_string, is_symbol,
+        is_vector, type_of,
+    },
+    varmap::{Env, EnvRef},
+};
 #[derive(Debug, Error)]
 pub enum TokensError<'a> {
     #[error("Invalid expression expected {expected:?},found {found:?}")]
     InvalidExpression { expected: &'a str, found: &'a str },
     #[error("Invalid expression")]
     Invalid,
+    #[error("Unbound symbol {0:?}")]
+    Unbound(&'a str),
+    #[error("Function expected {expected} arguments,found {found}")]
+    ArgsCount { expected: usize, found: usize },
+    #[error("Divide by zero")]
+    DivideByZero,
+    #[error("Number overflow")]
+    Overflow,
+    #[error("Generator is exhausted")]
+    StopIteration,
+    #[error("No pattern matched")]
+    NonExhaustive,
+    #[error("Index {index} out of bounds for length {len}")]
+    IndexOutOfBounds { index: usize, len: usize },
+    #[error("expected {expected}, got {found}")]
+    Type { expected: &'a str, found: &'a str },
+    ///参数的类型错误，位置从1开始，位置和函数名由`LispError::in_function`补充
+    #[error("expected {expected}, got {found}")]
+    ArgType { expected: &'a str, found: &'a str, index: usize },
+    ///读取输入时的错误，位置保存在`LispError`的span中
+    #[error("{0}")]
+    Syntax(&'a str),
+}
+impl TokensError<'_> {
+    ///错误类型，用于`catch`匹配
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TokensError::InvalidExpression { .. } => "invalid-expression",
+            TokensError::Invalid => "invalid",
+            TokensError::Unbound(_) => "unbound-symbol",
+            TokensError::ArgsCount { .. } => "arity-error",
+            TokensError::DivideByZero => "divide-by-zero",
+            TokensError::Overflow => "overflow",
+            TokensError::StopIteration => "stop-iteration",
+            TokensError::NonExhaustive => "match-error",
+            TokensError::IndexOutOfBounds { .. } => "index-error",
+            TokensError::Type { .. } | TokensError::ArgType { .. } => "type-error",
+            TokensError::Syntax(_) => "syntax",
+        }
+    }
 }
 pub type Num = u32;
+///内置函数，参数在调用之前已经计算过
+pub type Builtin = fn(&[Expr]) -> LispResult<Expr>;
+///可以保存数据的函数
+pub type NativeFn = Box<dyn Fn(&[Expr]) -> LispResult<Expr>>;
+///表达式在输入中的位置，按字符计算，不包含结束位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
 ///基础token
 #[derive(Debug, Clone)]
 pub enum Token {
@@ -18,6 +92,10 @@ pub enum Token {
     Number(String),
     ///运算符 '+' '-' '*' ...
     Operator(String),
+    ///字符串，保存包括引号和转义的原文 "a\"b"
+    Str(String),
+    ///字符，保存原文 #\a #\space #\x4E2D
+    Char(String),
     ///左括号 '('
     LeftBracket,
     ///右括号 ')'
@@ -26,6 +104,18 @@ pub enum Token {
     LeftBraces,
     ///右大括号 '}'
     RightBraces,
+    ///哈希表字面量的开始 '#{'
+    HashBraces,
+    ///左方括号 '['
+    LeftSquare,
+    ///右方括号 ']'
+    RightSquare,
+    ///反引号 '`'
+    Quasiquote,
+    ///逗号 ','
+    Unquote,
+    ///逗号加at ',@'
+    UnquoteSplicing,
     ///结束符
     Eof,
 }
@@ -33,11 +123,18 @@ impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Token::Number(v) => write!(f, "{}", v),
+            Token::Str(v) | Token::Char(v) => write!(f, "{}", v),
             Token::Operator(v) => write!(f, "{}", v),
             Token::LeftBracket => write!(f, "("),
             Token::RightBracket => write!(f, ")"),
             Token::LeftBraces => write!(f, "{{"),
             Token::RightBraces => write!(f, "}}"),
+            Token::HashBraces => write!(f, "#{{"),
+            Token::LeftSquare => write!(f, "["),
+            Token::RightSquare => write!(f, "]"),
+            Token::Quasiquote => write!(f, "`"),
+            Token::Unquote => write!(f, ","),
+            Token::UnquoteSplicing => write!(f, ",@"),
             Token::Eof => write!(f, ""),
         }
     }
@@ -47,24 +144,77 @@ impl fmt::Display for Token {
 pub enum Expr {
     ///数字类型
     Number(Num),
-    ///操作符树
-    Operator(String, Vec<Expr>),
+    ///操作符树，宏展开等生成的表达式没有位置
+    Operator(String, Vec<Expr>, Option<Span>),
     #[allow(clippy::enum_variant_names)]
-    QExpr(Vec<Expr>),
+    QExpr(List),
+    ///变量名
+    Symbol(String),
+    ///字符串，按字符处理
+    Str(Rc<str>),
+    ///单个Unicode字符
+    Char(char),
+    ///由Rust闭包实现的函数
+    Native(Rc<Native>),
+    ///`defrecord`定义的记录
+    Record(Rc<Record>),
+    ///用户定义的函数
+    Function(Rc<Function>),
+    ///宏，参数不计算，返回的代码再进行计算
+    Macro(Rc<Function>),
+    ///`syntax-rules`风格的宏
+    Syntax(Rc<SyntaxRules>),
+    ///call/cc捕获的continuation，可以像函数一样调用
+    Continuation(Rc<Continuation>),
+    ///错误对象
+    Error(Rc<LispError>),
+    ///生成器
+    Generator(Rc<Generator>),
+    ///不可变的哈希表
+    Map(Map),
+    ///可以按下标访问的向量
+    Vector(TrieVector),
+}
+///用户定义的函数，保存定义时的作用域
+pub struct Function {
+    pub params: Vec<String>,
+    pub body: Expr,
+    pub env: EnvRef,
+}
+///由Rust闭包实现的函数，例如`defrecord`生成的构造函数，可以保存内置函数不能保存的数据
+pub struct Native {
+    pub name: String,
+    pub func: NativeFn,
+    ///记录的构造函数保存记录的类型，模式匹配时按类型比较
+    pub record: Option<Rc<RecordType>>,
+}
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Native({})", self.name)
+    }
+}
+// 作用域中可能保存函数自身，打印作用域会无限递归
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("params", &self.params)
+            .field("body", &self.body)
+            .finish()
+    }
 }
 impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Expr::Number(v) => write!(f, "{}", v),
 
-            Expr::Operator(op, exprs) => {
+            Expr::Operator(op, exprs, _) => {
                 let mut s = Vec::new();
                 s.push(op.to_owned());
                 // 遍历里面的Expr
                 for i in exprs {
                     s.push(i.to_string());
                 }
-                write!(f, "{}", s.join(" "))
+                write!(f, "({})", s.join(" "))
             }
             Expr::QExpr(tokens) => {
                 // 如果是一个qexpr
@@ -78,98 +228,323 @@ impl fmt::Display for Expr {
 
                 write!(f, "{}", s.join(" "))
             }
+            Expr::Symbol(v) => write!(f, "{}", v),
+            Expr::Str(v) => write!(f, "\"{}\"", escape(v)),
+            Expr::Char(c) => match char_name(*c) {
+                Some(name) => write!(f, "#\\{}", name),
+                None if c.is_control() => write!(f, "#\\x{:X}", *c as u32),
+                None => write!(f, "#\\{}", c),
+            },
+            Expr::Function(func) => write!(f, "<function>"),
+            Expr::Native(native) => write!(f, "<builtin {}>", native.name),
+            // 输出构造函数的调用，可以重新计算得到相同的记录
+            Expr::Record(record) => {
+                let mut s = vec![record.ty.name.to_owned()];
+                s.extend(record.values.iter().map(|i| i.to_string()));
+                write!(f, "({})", s.join(" "))
+            }
+            Expr::Macro(func) => write!(f, "<macro>"),
+            Expr::Syntax(rules) => write!(f, "<syntax>"),
+            Expr::Continuation(k) => write!(f, "<continuation>"),
+            Expr::Error(err) => write!(f, "<error {}>", err),
+            Expr::Generator(_) => write!(f, "<generator>"),
+            Expr::Map(map) => {
+                let mut s = vec!["#{".to_string()];
+                for (k, v) in entries(map) {
+                    s.push(k.expr().to_string());
+                    s.push(v.to_string());
+                }
+                s.push("}".to_string());
+                write!(f, "{}", s.join(" "))
+            }
+            Expr::Vector(items) => {
+                let mut s = vec!["[".to_string()];
+                s.extend(items.iter().map(|i| i.to_string()));
+                s.push("]".to_string());
+                write!(f, "{}", s.join(" "))
+            }
         }
     }
 }
 
 impl Expr {
-    ///计算数学表达式，返回数字表达式
+    ///计算数学表达式，返回数字表达式，出错时panic
     pub fn calc(&self) -> Expr {
-        match self {
-            Expr::Number(v) => Expr::Number(*v),
-            Expr::Operator(op, exprs) => Self::switch(op, exprs),
-            Expr::QExpr(tokens) => Expr::QExpr(tokens.to_owned()),
-        }
+        self.eval(&Env::new()).unwrap_or_else(|err| panic!("{}", err))
+    }
+    ///在指定作用域中计算表达式
+    pub fn eval(&self, env: &EnvRef) -> LispResult<Expr> {
+        eval(self, env)
     }
     /// 获取计算结果
     pub fn math_result(&self) -> Num {
+        match self.calc() {
+            Expr::Number(v) => v,
+            Expr::QExpr(exprs) => panic!("Invalid expression,expect a number,but get a qexpr"),
+            _ => panic!("Invalid"),
+        }
+    }
+    ///把qexpr转换为可以计算的表达式，`{+ 1 2}`转换为`(+ 1 2)`，只有一个元素时就是元素本身
+    pub fn to_code(&self) -> LispResult<Expr> {
         match self {
-            Expr::Number(v) => v.to_owned(),
-            Expr::Operator(op, v) => {
-                if let Expr::Number(v) = Self::switch(op, v) {
-                    v
-                } else {
-                    panic!("Invalid")
+            Expr::QExpr(exprs) => match exprs.to_vec().as_slice() {
+                [] => Ok(Expr::QExpr(List::new())),
+                [v] => Ok(v.to_owned()),
+                [Expr::Symbol(op), args @ ..] => Ok(Expr::Operator(op.to_owned(), args.to_vec(), None)),
+                _ => Err(TokensError::InvalidExpression { expected: "symbol", found: "qexpr" }.into()),
+            },
+            _ => Ok(self.to_owned()),
+        }
+    }
+    ///把参数绑定到函数的作用域中，`{x & rest}`中`&`后面的变量接收剩余参数
+    pub fn bind(func: &Function, args: Vec<Expr>) -> LispResult<EnvRef> {
+        let local = Env::child(&func.env);
+        let mut args = args.into_iter();
+        let mut params = func.params.iter();
+        while let Some(name) = params.next() {
+            if name == "&" {
+                let rest = params
+                    .next()
+                    .ok_or(TokensError::InvalidExpression { expected: "symbol after &", found: "}" })?;
+                local.def(rest, Expr::QExpr(args.by_ref().collect()));
+                break;
+            }
+            match args.next() {
+                Some(arg) => local.def(name, arg),
+                None => {
+                    return Err(TokensError::ArgsCount {
+                        expected: func.params.len(),
+                        found: func.params.len() - params.len() - 1,
+                    }
+                    .into());
                 }
             }
-            Expr::QExpr(exprs) => panic!("Invalid expression,expect a number,but get a qexpr"),
         }
+        let extra = args.count();
+        if extra > 0 {
+            return Err(TokensError::ArgsCount {
+                expected: func.params.len(),
+                found: func.params.len() + extra,
+            }
+            .into());
+        }
+        Ok(local)
     }
 
-    // 通过迭代器的方式计算值,传入的如果是一个数那么直接返回，如果是一个表达式，通过递归调用直到遇到数字为止
-    pub fn switch(op: &str, v: &[Expr]) -> Expr {
-        match op {
-            "head" =>  head(v).to_owned(),
-            "+" => add(v),
-            "-"=>sub(v),
-            "*"=>mul(v),
-            "/"=>div(v),
-            "min"=>min(v),
-            "max"=>max(v),
-            "%"=>mmod(v),
-            "^"=>mi(v),
-            _ => {
-                panic!()
-            }
+    ///值的类型名，用在类型错误中，记录是`record`；`type-of`对记录返回记录自己的类型名
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Expr::Number(_) => "number",
+            Expr::Operator(..) => "sexpr",
+            Expr::QExpr(_) => "qexpr",
+            Expr::Symbol(_) => "symbol",
+            Expr::Str(_) => "string",
+            Expr::Char(_) => "char",
+            Expr::Function(_) | Expr::Native(_) => "function",
+            Expr::Record(_) => "record",
+            Expr::Macro(_) => "macro",
+            Expr::Syntax(_) => "syntax",
+            Expr::Continuation(_) => "continuation",
+            Expr::Error(_) => "error",
+            Expr::Generator(_) => "generator",
+            Expr::Map(_) => "map",
+            Expr::Vector(_) => "vector",
         }
     }
+    ///查找内置函数
+    pub fn builtin(op: &str) -> Option<Builtin> {
+        let func: Builtin = match op {
+            "head" => head,
+            "tail" => tail,
+            "cons" => cons,
+            "list" => list,
+            "+" => add,
+            "-" => sub,
+            "*" => mul,
+            "/" => div,
+            "min" => min,
+            "max" => max,
+            "%" => mmod,
+            "^" => mi,
+            "==" => eq,
+            "!=" => ne,
+            "<" => lt,
+            ">" => gt,
+            "<=" => le,
+            ">=" => ge,
+            "range" => range,
+            "throw" => throw,
+            "error-kind" => error_kind,
+            "error-message" => error_message,
+            "error-value" => error_value,
+            "error-span" => error_span,
+            "hash-map" => hash_map,
+            "get" => get,
+            "assoc" => assoc,
+            "dissoc" => dissoc,
+            "keys" => keys,
+            "vals" => vals,
+            "contains?" => contains,
+            "merge" => merge,
+            "str-len" => str_len,
+            "concat" => concat,
+            "substr" => substr,
+            "split" => split,
+            "join" => join,
+            "trim" => trim,
+            "upper" => upper,
+            "lower" => lower,
+            "replace" => replace,
+            "starts-with?" => starts_with,
+            "index-of" => index_of,
+            "str->num" => str_to_num,
+            "num->str" => num_to_str,
+            "chars" => chars,
+            "char->int" => char_to_int,
+            "int->char" => int_to_char,
+            "char-alphabetic?" => char_alphabetic,
+            "char-upper" => char_upper,
+            "char-lower" => char_lower,
+            "vector" => vector,
+            "nth" => nth,
+            "vset" => vset,
+            "vpush" => vpush,
+            "vlen" => vlen,
+            "subvec" => subvec,
+            "type-of" => type_of,
+            "equal?" => is_equal,
+            "eq?" => is_eq,
+            "number?" => is_number,
+            "list?" => is_list,
+            "fn?" => is_fn,
+            "string?" => is_string,
+            "symbol?" => is_symbol,
+            "char?" => is_char,
+            "map?" => is_map,
+            "vector?" => is_vector,
+            "error?" => is_error,
+            "generator?" => is_generator,
+            "record?" => is_record,
+            _ => return None,
+        };
+        Some(func)
+    }
 }
 
 #[derive(Debug)]
 pub struct Tokens {
     tokens: VecDeque<Token>,
+    // 每个token在输入中的位置
+    spans: VecDeque<Span>,
+    // 上一个读取的token的位置
+    last: Span,
+}
+
+// 读取时的语法错误，类型是`syntax`
+fn syntax_error(message: &str, span: Span) -> Box<LispError> {
+    Box::<LispError>::from(TokensError::Syntax(message)).with_span(Some(span))
+}
+///读取一个表达式，词法和语法错误都返回`syntax`错误
+pub fn read(s: &str) -> LispResult<Expr> {
+    Tokens::new(s.to_string())?.parser()
 }
 
 impl Tokens {
-    pub fn new(s: String) -> Tokens {
+    ///词法分析，遇到不能识别的字符或者没有结束的字符串时返回`syntax`错误
+    pub fn new(s: String) -> LispResult<Tokens> {
         let mut deq = VecDeque::new();
+        let mut spans = VecDeque::new();
         let mut iter = s.chars().peekable();
+        // 当前字符的位置
+        let mut pos = 0;
         while let Some(v) = iter.peek() {
-            let c = v;
-            match c {
-                '0'..='9' => {
-                    let s = Self::parser_number_string(&mut iter);
-                    deq.push_back(Token::Number(s));
-                    continue;
+            let c = *v;
+            let token = match c {
+                '0'..='9' => Token::Number(Self::parser_number_string(&mut iter)),
+                '"' => match Self::str_literal(&mut iter) {
+                    Some(s) => Token::Str(s),
+                    None => {
+                        let end = s.chars().count();
+                        return Err(syntax_error("unterminated string", Span { start: pos, end }));
+                    }
+                },
+                'a'..='z' | 'A'..='Z' | '_' | '=' | '<' | '>' | '!' | '.' => {
+                    Token::Operator(Self::str_parser(&mut iter))
                 }
-                'a'..='z' => {
-                    let s = Self::str_parser(&mut iter);
-                    deq.push_back(Token::Operator(s));
+                '+' | '-' | '*' | '/' | '%' | '^' | '\\' | '&' => {
+                    iter.next();
+                    Token::Operator(c.to_string())
                 }
-                '+' | '-' | '*' | '/' | '%' | '^' => {
-                    deq.push_back(Token::Operator(c.to_string()));
+                '`' => {
+                    iter.next();
+                    Token::Quasiquote
+                }
+                ',' => {
+                    iter.next();
+                    if let Some('@') = iter.peek() {
+                        iter.next();
+                        Token::UnquoteSplicing
+                    } else {
+                        Token::Unquote
+                    }
                 }
                 '(' => {
-                    deq.push_back(Token::LeftBracket);
+                    iter.next();
+                    Token::LeftBracket
                 }
                 ')' => {
-                    deq.push_back(Token::RightBracket);
+                    iter.next();
+                    Token::RightBracket
+                }
+                ' ' | '\t' | '\n' | '\r' => {
+                    iter.next();
+                    pos += 1;
+                    continue;
                 }
-                ' ' => {}
                 '{' => {
-                    deq.push_back(Token::LeftBraces);
+                    iter.next();
+                    Token::LeftBraces
                 }
                 '}' => {
-                    deq.push_back(Token::RightBraces);
+                    iter.next();
+                    Token::RightBraces
+                }
+                '[' => {
+                    iter.next();
+                    Token::LeftSquare
+                }
+                ']' => {
+                    iter.next();
+                    Token::RightSquare
+                }
+                '#' => {
+                    iter.next();
+                    match iter.next() {
+                        Some('{') => Token::HashBraces,
+                        Some('\\') => match Self::char_literal(&mut iter) {
+                            Some(raw) => Token::Char(raw),
+                            None => return Err(syntax_error("missing character name", Span { start: pos, end: pos + 2 })),
+                        },
+                        _ => return Err(syntax_error("unknown character '#'", Span { start: pos, end: pos + 1 })),
+                    }
                 }
                 _ => {
-                    panic!("Unknown character")
+                    let message = format!("unknown character {:?}", c);
+                    return Err(syntax_error(&message, Span { start: pos, end: pos + 1 }));
                 }
-            }
-            iter.next();
+            };
+            // token的文本长度就是读取的字符数
+            let start = pos;
+            pos += token.to_string().chars().count();
+            deq.push_back(token);
+            spans.push_back(Span { start, end: pos });
         }
 
-        Self { tokens: deq }
+        Ok(Self {
+            tokens: deq,
+            spans,
+            last: Span { start: 0, end: 0 },
+        })
     }
     // 将连接在一起的字符组成字符串
     pub fn str_parser<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> String {
@@ -179,7 +554,7 @@ impl Tokens {
         }
         while let Some(v) = iter.peek() {
             match v {
-                'a'..='z' => {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' | '*' | '/' | '?' | '!' | '<' | '>' | '=' | '.' => {
                     s.push(v.to_owned());
                     iter.next();
                 }
@@ -190,10 +565,53 @@ impl Tokens {
         }
         s
     }
+    // 读取`#\`后面的字符名，第一个字符总是读取，后面连续的字母和数字组成名字，已经到结尾时返回None
+    fn char_literal<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> Option<String> {
+        let mut s = "#\\".to_string();
+        s.push(iter.next()?);
+        while let Some(c) = iter.peek()
+            && c.is_ascii_alphanumeric()
+        {
+            s.push(*c);
+            iter.next();
+        }
+        Some(s)
+    }
+    // 读取字符串字面量的原文，转义在转换表达式时处理，没有结束的引号时返回None
+    fn str_literal<I: Iterator<Item = char>>(iter: &mut Peekable<I>) -> Option<String> {
+        let mut s = String::new();
+        if let Some(v) = iter.next() {
+            s.push(v);
+        }
+        loop {
+            match iter.next()? {
+                '"' => {
+                    s.push('"');
+                    return Some(s);
+                }
+                '\\' => {
+                    s.push('\\');
+                    s.push(iter.next()?);
+                }
+                c => s.push(c),
+            }
+        }
+    }
     /// 获取下一个tokens
     pub fn next(&mut self) -> Token {
+        if let Some(span) = self.spans.pop_front() {
+            self.last = span;
+        }
         self.tokens.pop_front().unwrap_or(Token::Eof)
     }
+    // 下一个token的开始位置
+    fn peek_start(&self) -> usize {
+        self.spans.front().map(|i| i.start).unwrap_or(self.last.end)
+    }
+    // 输入结束的位置，用于报告缺少的表达式
+    fn eof_span(&self) -> Span {
+        Span { start: self.last.end, end: self.last.end }
+    }
     ///查看下一个token
     pub fn peek(&self) -> &Token {
         self.tokens.front().unwrap_or(&Token::Eof)
@@ -218,86 +636,215 @@ impl Tokens {
         }
         s
     }
+    // 数字、字符串和字符字面量，位置是刚读取的token的位置
+    fn literal(&self, token: &Token) -> LispResult<Expr> {
+        Ok(match token {
+            Token::Number(v) => Expr::Number(v.parse().map_err(|_| syntax_error("number too large", self.last))?),
+            Token::Str(v) => Expr::Str(unescape(v).into()),
+            Token::Char(v) => {
+                let message = format!("unknown character name {}", v);
+                Expr::Char(parse_char(v).ok_or_else(|| syntax_error(&message, self.last))?)
+            }
+            _ => return Err(syntax_error("expected literal", self.last)),
+        })
+    }
+    // 读取左括号之后的表达式，跳过对应的右括号
+    fn parser_bracket(&mut self) -> LispResult<Expr> {
+        let value = self.parser()?;
+        if let Token::RightBracket = self.peek() {
+            self.next();
+        }
+        Ok(value)
+    }
     /// 转换qexpr表达式
-    pub fn parser_qexpr(&mut self) -> Expr {
+    pub fn parser_qexpr(&mut self) -> LispResult<Expr> {
+        Ok(Expr::QExpr(self.parser_items()?.into()))
+    }
+    /// 读取向量字面量，`[1 2 3]`中的元素不会被计算
+    pub fn parser_vector(&mut self) -> LispResult<Expr> {
+        Ok(Expr::Vector(self.parser_items()?.into_iter().collect()))
+    }
+    // 读取到右大括号或者右方括号为止，调用之前已经读取了左括号
+    fn parser_items(&mut self) -> LispResult<Vec<Expr>> {
+        let open = self.last;
         let mut vec = Vec::new();
         loop {
-            match self.next() {
-                Token::Number(v) => vec.push(Expr::Number(v.parse().expect("Invalid"))),
-                Token::LeftBraces => {
-                    let v = self.parser_qexpr();
-                    vec.push(v);
-                }
-                Token::RightBraces => {
-                    break;
+            let value = match self.next() {
+                token @ (Token::Number(_) | Token::Str(_) | Token::Char(_)) => self.literal(&token)?,
+                Token::LeftBraces => self.parser_qexpr()?,
+                Token::HashBraces => self.parser_map()?,
+                Token::LeftSquare => self.parser_vector()?,
+                Token::RightBraces | Token::RightSquare => break,
+                Token::Eof => {
+                    return Err(syntax_error("unclosed bracket", Span { start: open.start, end: self.last.end }));
                 }
-                Token::Eof => break,
-                Token::LeftBracket => vec.push(self.parser()),
+                Token::LeftBracket => self.parser()?,
                 Token::RightBracket => continue,
-                Token::Operator(_) => panic!("Invalid"),
+                Token::Operator(v) => Expr::Symbol(v),
+                Token::Quasiquote => self.parser_quote("quasiquote")?,
+                Token::Unquote => self.parser_quote("unquote")?,
+                Token::UnquoteSplicing => self.parser_quote("unquote-splicing")?,
             };
+            vec.push(value);
         }
-        Expr::QExpr(vec)
+        Ok(vec)
+    }
+    /// 读取哈希表字面量，`#{a 1 b 2}`中的键和值都不会被计算
+    pub fn parser_map(&mut self) -> LispResult<Expr> {
+        let start = self.last.start;
+        let items = self.parser_items()?;
+        let map = from_pairs(&items).map_err(|err| syntax_error(&err.message, Span { start, end: self.last.end }))?;
+        Ok(Expr::Map(map))
+    }
+    /// 读取引号后面的一个表达式，`` `x ``转换为`(quasiquote x)`
+    pub fn parser_quote(&mut self, name: &str) -> LispResult<Expr> {
+        let value = match self.next() {
+            token @ (Token::Number(_) | Token::Str(_) | Token::Char(_)) => self.literal(&token)?,
+            Token::Operator(v) => Expr::Symbol(v),
+            Token::LeftBraces => self.parser_qexpr()?,
+            Token::HashBraces => self.parser_map()?,
+            Token::LeftSquare => self.parser_vector()?,
+            Token::LeftBracket => self.parser_bracket()?,
+            Token::Quasiquote => self.parser_quote("quasiquote")?,
+            Token::Unquote => self.parser_quote("unquote")?,
+            Token::UnquoteSplicing => self.parser_quote("unquote-splicing")?,
+            Token::Eof => return Err(syntax_error(&format!("missing expression after {}", name), self.eof_span())),
+            Token::RightBracket | Token::RightBraces | Token::RightSquare => {
+                return Err(syntax_error(&format!("missing expression after {}", name), self.last));
+            }
+        };
+        Ok(Expr::Operator(name.to_string(), vec![value], None))
     }
-    // 转换为数学表达式树
-    pub fn parser(&mut self) -> Expr {
+    ///转换为数学表达式树，输入为空或者以右括号开始时返回`syntax`错误
+    pub fn parser(&mut self) -> LispResult<Expr> {
+        let start = self.peek_start();
         // 第一次读取表达式一定是运算符或者括号
         let mut op = match self.next() {
-            Token::Number(v) => Expr::Number(v.parse().expect("Invalid expression")),
-            Token::Operator(v) => Expr::Operator(v, vec![]),
-            Token::LeftBracket => self.parser(),
-            Token::RightBracket => {
-                panic!("Invalid expression,first token should is operator or left-bracket.")
+            token @ (Token::Number(_) | Token::Str(_) | Token::Char(_)) => self.literal(&token)?,
+            Token::Operator(v) => Expr::Operator(v, vec![], None),
+            Token::LeftBracket => self.parser()?,
+            Token::Eof => return Err(syntax_error("empty expression", self.eof_span())),
+            Token::LeftBraces => self.parser_qexpr()?,
+            Token::HashBraces => self.parser_map()?,
+            Token::LeftSquare => self.parser_vector()?,
+            token @ (Token::RightBracket | Token::RightBraces | Token::RightSquare) => {
+                return Err(syntax_error(&format!("unexpected {}", token), self.last));
             }
-            Token::Eof => panic!("expression can't be empty."),
-            Token::LeftBraces => self.parser_qexpr(),
-            Token::RightBraces => panic!("Invalid"),
+            Token::Quasiquote => self.parser_quote("quasiquote")?,
+            Token::Unquote => self.parser_quote("unquote")?,
+            Token::UnquoteSplicing => self.parser_quote("unquote-splicing")?,
         };
-        // 如果op是一个操作符，目前并不清楚其他情况
-        if let Expr::Operator(_, vec) = &mut op {
+        // 如果op是一个操作符，读取后面的参数直到右括号或者输入结束
+        if let Expr::Operator(_, vec, span) = &mut op {
             loop {
-                if let Token::Eof = self.peek() {
-                    break;
-                } else {
-                    let value = match self.peek() {
-                        Token::Number(v) => Expr::Number(
-                            // 将字符串转换为数字
-                            v.parse().expect("Invalid expression,the number is invalid"),
-                        ),
-                        Token::Operator(v) => {
-                            panic!("{}",TokensError::InvalidExpression { expected: "number", found: v.as_str() })
-                        }
-                        Token::LeftBracket => {
-                            // 跳过这个左括号，可以少递归一层
-                            self.next();
-                            self.parser()
-                        }
-                        Token::RightBracket => {
-                            return op;
-                        }
-                        Token::Eof => {
-                            panic!("{}",TokensError::InvalidExpression { expected: "number", found: "Eof" })
-                        }
-                        Token::LeftBraces => {
-                            self.next();
-                            self.parser_qexpr()
-                        }
-                        Token::RightBraces => break,
-                    };
-                    
-                    vec.push(value);
-                    self.next();
-                }
+                let value = match self.peek() {
+                    Token::Eof | Token::RightBracket | Token::RightBraces | Token::RightSquare => break,
+                    Token::Operator(v) => {
+                        let value = Expr::Symbol(v.to_owned());
+                        self.next();
+                        value
+                    }
+                    Token::Number(_) | Token::Str(_) | Token::Char(_) => {
+                        let token = self.next();
+                        self.literal(&token)?
+                    }
+                    Token::LeftBracket => {
+                        // 跳过这个左括号，可以少递归一层
+                        self.next();
+                        self.parser_bracket()?
+                    }
+                    // qexpr会读取到右大括号为止，不需要再跳过
+                    Token::LeftBraces => {
+                        self.next();
+                        self.parser_qexpr()?
+                    }
+                    Token::HashBraces => {
+                        self.next();
+                        self.parser_map()?
+                    }
+                    Token::LeftSquare => {
+                        self.next();
+                        self.parser_vector()?
+                    }
+                    Token::Quasiquote | Token::Unquote | Token::UnquoteSplicing => {
+                        let name = match self.next() {
+                            Token::Quasiquote => "quasiquote",
+                            Token::Unquote => "unquote",
+                            _ => "unquote-splicing",
+                        };
+                        // 引号后面的表达式会被完整读取，不需要再跳过
+                        self.parser_quote(name)?
+                    }
+                };
+                vec.push(value);
+            }
+            *span = Some(Span {
+                start,
+                end: self.last.end,
+            });
+        }
+        Ok(op)
+    }
+}
+///读取字符串时处理转义，`\n` `\t` `\"` `\\`
+fn unescape(raw: &str) -> String {
+    let mut s = String::new();
+    let mut chars = raw[1..raw.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => s.push('\n'),
+            Some('t') => s.push('\t'),
+            Some('r') => s.push('\r'),
+            Some(c) => s.push(c),
+            None => {}
+        }
+    }
+    s
+}
+// 有名字的字符
+const CHAR_NAMES: [(&str, char); 5] = [("space", ' '), ("newline", '\n'), ("tab", '\t'), ("return", '\r'), ("nul", '\0')];
+fn char_name(c: char) -> Option<&'static str> {
+    CHAR_NAMES.iter().find(|(_, v)| *v == c).map(|(name, _)| *name)
+}
+///读取字符字面量，`#\a`、`#\space`、`#\x4E2D`，名字不存在或者编码无效时返回None
+fn parse_char(raw: &str) -> Option<char> {
+    let name = &raw[2..];
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(c);
+    }
+    if let Some((_, c)) = CHAR_NAMES.iter().find(|(n, _)| *n == name) {
+        return Some(*c);
+    }
+    name.strip_prefix('x').and_then(|hex| u32::from_str_radix(hex, 16).ok()).and_then(char::from_u32)
+}
+///输出字符串时转义，输出的结果可以重新读取
+pub fn escape(s: &str) -> String {
+    let mut result = String::new();
+    for c in s.chars() {
+        match c {
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            '\r' => result.push_str("\\r"),
+            '"' | '\\' => {
+                result.push('\\');
+                result.push(c);
             }
+            c => result.push(c),
         }
-        op
     }
+    result
 }
+
 #[test]
 fn test() {
-    let mut tokens = Tokens::new("% 2 2".to_string());
+    let mut tokens = Tokens::new("% 2 2".to_string()).unwrap();
     println!("{:?}", tokens);
-    let expr = tokens.parser();
+    let expr = tokens.parser().unwrap();
     println!("{:?}", expr);
     let v = expr.math_result();
     assert_eq!(0, v);
@@ -305,9 +852,9 @@ fn test() {
 }
 #[test]
 fn simple() {
-    let mut tokens = Tokens::new("+ 1 2".to_string());
+    let mut tokens = Tokens::new("+ 1 2".to_string()).unwrap();
     println!("{:?}", tokens);
-    let expr = tokens.parser();
+    let expr = tokens.parser().unwrap();
     println!("{:?}", expr);
     let v = expr.math_result();
     assert_eq!(3, v);
@@ -315,9 +862,9 @@ fn simple() {
 }
 #[test]
 fn bracket() {
-    let mut tokens = Tokens::new("(+ 1 2)".to_string());
+    let mut tokens = Tokens::new("(+ 1 2)".to_string()).unwrap();
     println!("{:?}", tokens);
-    let expr = tokens.parser();
+    let expr = tokens.parser().unwrap();
     println!("{:?}", expr);
     let v = expr.math_result();
     assert_eq!(3, v);
@@ -325,9 +872,9 @@ fn bracket() {
 }
 #[test]
 fn long_number() {
-    let mut tokens = Tokens::new("+ 12 2 2".to_string());
+    let mut tokens = Tokens::new("+ 12 2 2".to_string()).unwrap();
     println!("{:?}", tokens);
-    let expr = tokens.parser();
+    let expr = tokens.parser().unwrap();
     println!("{:?}", expr);
     let v = expr.math_result();
     assert_eq!(16, v);
@@ -335,9 +882,9 @@ fn long_number() {
 }
 #[test]
 fn operator_test() {
-    let mut tokens = Tokens::new("^ (% 8 3) 2".to_string());
+    let mut tokens = Tokens::new("^ (% 8 3) 2".to_string()).unwrap();
     println!("tokens: {:?}", tokens);
-    let expr = tokens.parser();
+    let expr = tokens.parser().unwrap();
     println!("expr: {:?}", expr);
     let v = expr.math_result();
     assert_eq!(4, v);
@@ -346,9 +893,9 @@ fn operator_test() {
 
 #[test]
 fn long_expression() {
-    let mut tokens = Tokens::new("+ (% 18 2) (+ 5 2)".to_string());
+    let mut tokens = Tokens::new("+ (% 18 2) (+ 5 2)".to_string()).unwrap();
     println!("tokens: {:?}", tokens);
-    let expr = tokens.parser();
+    let expr = tokens.parser().unwrap();
     println!("expr: {:?}", expr);
     let v = expr.math_result();
     assert_eq!(7, v);
@@ -356,9 +903,9 @@ fn long_expression() {
 }
 #[test]
 fn long_expression2() {
-    let mut tokens = Tokens::new("* 22 4 (+ 0 0 0)".to_string());
+    let mut tokens = Tokens::new("* 22 4 (+ 0 0 0)".to_string()).unwrap();
     println!("tokens: {:?}", tokens);
-    let expr = tokens.parser();
+    let expr = tokens.parser().unwrap();
     println!("expr: {:?}", expr);
     let v = expr.math_result();
     assert_eq!(0, v);
@@ -366,9 +913,9 @@ fn long_expression2() {
 }
 #[test]
 fn str_expr() {
-    let mut tokens = Tokens::new("max 1 2 3".to_string());
+    let mut tokens = Tokens::new("max 1 2 3".to_string()).unwrap();
     println!("tokens: {:?}", tokens);
-    let expr = tokens.parser();
+    let expr = tokens.parser().unwrap();
     println!("expr: {:?}", expr);
     let v = expr.math_result();
     assert_eq!(3, v);
@@ -376,20 +923,47 @@ fn str_expr() {
 }
 #[test]
 fn qexpr() {
-    let mut tokens = Tokens::new("{1 2 3 {1 2 3}}".to_string());
+    let mut tokens = Tokens::new("{1 2 3 {1 2 3}}".to_string()).unwrap();
     println!("tokens: {:?}", tokens);
-    let expr = tokens.parser();
+    let expr = tokens.parser().unwrap();
     println!("expr: {:?}", expr);
     let v = expr.calc();
     println!("{}", v);
 }
 #[test]
 fn qexpr2() {
-    let mut tokens = Tokens::new("head { (1)2 { 3 4 }}".to_string());
+    let mut tokens = Tokens::new("head { (1)2 { 3 4 }}".to_string()).unwrap();
     println!("tokens: {:?}", tokens);
-    let expr = tokens.parser();
+    let expr = tokens.parser().unwrap();
     println!("expr: {:?}", expr);
     let v = expr.calc();
     println!("{}", v);
 
 }
+#[test]
+fn syntax_error_test() {
+    let err = |s: &str| read(s).unwrap_err();
+    for input in ["+ 1 @", "\"abc", "#{a 1 b}", "", "  ", "] 1", "} 1", "+ 1 `", "{1 2", "+ 1 99999999999", "#x"] {
+        assert_eq!("syntax", err(input).kind, "{}", input);
+    }
+    assert_eq!(Some(Span { start: 4, end: 5 }), err("+ 1 @").span);
+    assert_eq!(Some(Span { start: 2, end: 6 }), err("+ \"abc").span);
+    assert_eq!(Some(Span { start: 0, end: 8 }), err("#{a 1 b}").span);
+    assert_eq!(Some(Span { start: 0, end: 1 }), err("]").span);
+    assert_eq!("syntax: unexpected } at 0..1", err("} 1").to_string());
+    assert_eq!("syntax: empty expression at 0..0", err("").to_string());
+    assert_eq!("syntax: missing expression after quasiquote at 5..5", err("+ 1 `").to_string());
+    // 正确的输入不受影响
+    assert_eq!("{ 1 { 2 } }", read("{1 {2}}").unwrap().to_string());
+}
+#[test]
+fn char_error_test() {
+    let err = |s: &str| read(s).unwrap_err();
+    assert_eq!("syntax: unknown character name #\\xyz at 5..10", err("list #\\xyz").to_string());
+    assert_eq!("syntax: unknown character name #\\12 at 0..4", err("#\\12").to_string());
+    // 编码超出范围或者是代理项
+    assert_eq!("syntax", err("#\\x110000").kind);
+    assert_eq!("syntax", err("#\\xD800").kind);
+    assert_eq!("syntax: missing character name at 5..7", err("list #\\").to_string());
+    assert!(matches!(read("#\\x4E2D").unwrap(), Expr::Char('中')));
+}
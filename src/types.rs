@@ -0,0 +1,84 @@
+use crate::{
+    calc::args_count,
+    error::LispResult,
+    token::{Expr, Num},
+};
+
+///`type-of 1`返回符号`number`，记录返回定义时的类型名
+pub fn type_of(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::Symbol(match &tokens[0] {
+        Expr::Record(record) => record.ty.name.to_owned(),
+        value => value.type_name().to_string(),
+    }))
+}
+// 判断唯一的参数是否满足条件，返回1或者0
+fn is(tokens: &[Expr], pred: fn(&Expr) -> bool) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::Number(pred(&tokens[0]) as Num))
+}
+pub fn is_number(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::Number(_)))
+}
+pub fn is_list(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::QExpr(_)))
+}
+///可以调用的值，包括continuation
+pub fn is_fn(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::Function(_) | Expr::Native(_) | Expr::Continuation(_)))
+}
+pub fn is_string(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::Str(_)))
+}
+pub fn is_symbol(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::Symbol(_)))
+}
+pub fn is_char(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::Char(_)))
+}
+pub fn is_map(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::Map(_)))
+}
+pub fn is_vector(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::Vector(_)))
+}
+pub fn is_error(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::Error(_)))
+}
+pub fn is_generator(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::Generator(_)))
+}
+pub fn is_record(tokens: &[Expr]) -> LispResult<Expr> {
+    is(tokens, |i| matches!(i, Expr::Record(_)))
+}
+
+#[cfg(test)]
+fn run(s: &str) -> LispResult<Expr> {
+    crate::token::read(s).and_then(|expr| expr.eval(&crate::varmap::Env::new()))
+}
+#[test]
+fn type_of_test() {
+    let names = ["number", "qexpr", "string", "char", "function", "map", "vector", "symbol"];
+    let values = ["1", "{1 2}", "\"a\"", "#\\a", "(\\ {x} {x})", "#{1 2}", "[1]", "`a"];
+    for (name, value) in names.iter().zip(values) {
+        assert_eq!(*name, run(&format!("type-of {}", value)).unwrap().to_string());
+    }
+    assert_eq!("point", run("do (defrecord point {x y}) (type-of (point 1 2))").unwrap().to_string());
+    assert!(matches!(run("number? 1").unwrap(), Expr::Number(1)));
+    assert!(matches!(run("list? [1]").unwrap(), Expr::Number(0)));
+    assert!(matches!(run("fn? (\\ {x} {x})").unwrap(), Expr::Number(1)));
+    assert!(matches!(run("string? \"\"").unwrap(), Expr::Number(1)));
+}
+#[test]
+fn type_error_test() {
+    let err = run("+ 1 {2}").unwrap_err();
+    assert_eq!("type-error", err.kind);
+    assert_eq!("expected number, got qexpr at argument 2 of +", err.message);
+    let err = run("- 5 1 \"a\"").unwrap_err();
+    assert_eq!("expected number, got string at argument 3 of -", err.message);
+    assert_eq!("expected qexpr, got number at argument 2 of cons", run("cons 1 2").unwrap_err().message);
+    assert_eq!("expected number, got char at argument 1 of range", run("range #\\a").unwrap_err().message);
+    // 捕获之后可以按类型匹配
+    let v = run("try (< 1 {}) (catch type-error {e} (error-message e))").unwrap();
+    assert_eq!(Expr::Str("expected number, got qexpr at argument 2 of <".into()), v);
+}
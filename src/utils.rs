@@ -1,26 +1,80 @@
-mod string {
-    pub fn find<S: AsRef<str>>(s: S, ns: S) -> Option<usize> {
-        let s = s.as_ref();
-        let ns = ns.as_ref();
-        let ns_len = ns.len();
-        let s_len = s.len();
-        if ns_len == 0 || s_len == 0 || ns_len > s_len {
-            return None;
+pub mod string {
+    // UTF-8中一个字符的编码不会是另一个字符编码的一部分，按字节匹配得到的位置一定在字符边界上
+    ///KMP查找所有出现的位置，返回字节位置
+    pub struct Matches<'a> {
+        text: &'a [u8],
+        pattern: &'a [u8],
+        // 每个前缀的最长相同前后缀长度
+        table: Vec<usize>,
+        // 下一个要比较的文本位置和已经匹配的长度
+        index: usize,
+        matched: usize,
+        overlapping: bool,
+    }
+    impl<'a> Matches<'a> {
+        pub fn new(s: &'a str, ns: &'a str, overlapping: bool) -> Self {
+            let pattern = ns.as_bytes();
+            let mut table = vec![0; pattern.len()];
+            let mut k = 0;
+            for i in 1..pattern.len() {
+                while k > 0 && pattern[i] != pattern[k] {
+                    k = table[k - 1];
+                }
+                if pattern[i] == pattern[k] {
+                    k += 1;
+                }
+                table[i] = k;
+            }
+            Self { text: s.as_bytes(), pattern, table, index: 0, matched: 0, overlapping }
         }
-        let mut left = 0;
-        let mut right = 0;
-        while left < s_len {
-            if s[left..left + 1] != ns[right..right + 1] {
-                right = 0;
-            } else {
-                right += 1;
-                if right == ns_len {
-                    return Some(left - ns_len + 1);
+    }
+    impl Iterator for Matches<'_> {
+        type Item = usize;
+        fn next(&mut self) -> Option<usize> {
+            if self.pattern.is_empty() {
+                return None;
+            }
+            while self.index < self.text.len() {
+                let c = self.text[self.index];
+                while self.matched > 0 && c != self.pattern[self.matched] {
+                    self.matched = self.table[self.matched - 1];
+                }
+                if c == self.pattern[self.matched] {
+                    self.matched += 1;
+                }
+                self.index += 1;
+                if self.matched == self.pattern.len() {
+                    let start = self.index - self.matched;
+                    // 不重叠时从匹配结束的位置重新开始
+                    self.matched = if self.overlapping { self.table[self.matched - 1] } else { 0 };
+                    return Some(start);
                 }
             }
-            left += 1;
+            None
         }
-        None
+    }
+    ///第一次出现的字节位置，ns为空时返回None
+    pub fn find<S: AsRef<str>>(s: S, ns: S) -> Option<usize> {
+        Matches::new(s.as_ref(), ns.as_ref(), false).next()
+    }
+    ///最后一次出现的字节位置
+    pub fn rfind<S: AsRef<str>>(s: S, ns: S) -> Option<usize> {
+        Matches::new(s.as_ref(), ns.as_ref(), true).last()
+    }
+    ///所有出现的字节位置，overlapping为true时`aa`在`aaa`中出现两次
+    pub fn find_all<S: AsRef<str>>(s: S, ns: S, overlapping: bool) -> Vec<usize> {
+        Matches::new(s.as_ref(), ns.as_ref(), overlapping).collect()
+    }
+    ///按ns分割，ns为空时返回整个字符串
+    pub fn split_on<'a>(s: &'a str, ns: &str) -> Vec<&'a str> {
+        let mut parts = Vec::new();
+        let mut last = 0;
+        for start in Matches::new(s, ns, false) {
+            parts.push(&s[last..start]);
+            last = start + ns.len();
+        }
+        parts.push(&s[last..]);
+        parts
     }
 }
 #[test]
@@ -39,4 +93,20 @@ fn find_str() {
     let ns = "mine";
     let res = find(s, ns);
     assert_eq!(None, res);
-}
\ No newline at end of file
+}
+#[test]
+fn kmp_test() {
+    use crate::utils::string::{find, find_all, rfind, split_on};
+
+    // 朴素算法重置时会漏掉这种情况
+    assert_eq!(Some(1), find("aaab", "aab"));
+    assert_eq!(Some(9), find("日志：错误发生", "错误"));
+    assert_eq!(None, find("中文", ""));
+    assert_eq!(Some(4), rfind("abcabc", "bc"));
+    assert_eq!(vec![0, 1, 2], find_all("aaaa", "aa", true));
+    assert_eq!(vec![0, 2], find_all("aaaa", "aa", false));
+    assert_eq!(vec![0, 7], find_all("错误,错误", "错误", false));
+    assert_eq!(vec!["a", "b", "", "c"], split_on("a,,b,,,,c", ",,"));
+    assert_eq!(vec!["中", "文"], split_on("中和文", "和"));
+    assert_eq!(vec!["abc"], split_on("abc", ""));
+}
@@ -1,8 +1,13 @@
 use std::{
-    cell::RefCell,
-    collections::{HashMap, VecDeque},
+    cell::{Cell, RefCell},
+    collections::HashMap,
     rc::{Rc, Weak},
 };
+
+use crate::{
+    serial::{self, Reader, SerialError, put_u32, read_expr, u32_at, write_expr},
+    token::Expr,
+};
 type NodeRef = Rc<RefCell<VarNode>>;
 type NodeWeakRef = Weak<RefCell<VarNode>>;
 // 8+8+8+8=32byte
@@ -12,6 +17,8 @@ pub struct VarNode {
     // 子节点
     child: HashMap<char, Rc<RefCell<VarNode>>>,
     is_word: bool,
+    // 变量绑定的值
+    value: Option<Expr>,
 }
 
 impl VarNode {
@@ -20,11 +27,33 @@ impl VarNode {
         let sself = Self {
             is_word: false,
             child: HashMap::new(),
+            value: None,
         };
         Rc::new(RefCell::new(sself))
     }
     // 获取当前节点的前一个节点
 
+    // 查找字符串对应的节点
+    fn find<S: AsRef<str>>(node: &NodeRef, s: S) -> Option<NodeRef> {
+        let mut current_node = node.clone();
+        for c in s.as_ref().chars() {
+            let next = current_node.borrow().child.get(&c)?.clone();
+            current_node = next;
+        }
+        Some(current_node)
+    }
+    ///获取变量绑定的值
+    pub fn get<S: AsRef<str>>(node: &NodeRef, s: S) -> Option<Expr> {
+        let node = Self::find(node, s)?;
+        node.borrow().value.clone()
+    }
+    ///插入变量，已经存在的变量会被覆盖
+    pub fn insert<S: AsRef<str>>(node: &NodeRef, s: S, value: Expr) {
+        VarNode::push_str(node, &s);
+        if let Some(node) = Self::find(node, s) {
+            node.borrow_mut().value = Some(value);
+        }
+    }
     // 匹配字符串
     pub fn pattern<S: AsRef<str>>(node: &NodeRef, s: S) -> bool {
         let s = s.as_ref();
@@ -50,13 +79,13 @@ impl VarNode {
     ///插入查找词
     pub fn push_str<S: AsRef<str>>(node: &NodeRef, s: S) {
         let s = s.as_ref();
-        let len = s.len();
+        // 按字符计算长度，和下面的下标一致
+        let len = s.chars().count();
         if len == 0 {
             return;
         }
         // 当前节点等于传入的节点
         let mut current_node = node.clone();
-        let iter = s.chars();
         for (index, c) in s.chars().enumerate() {
             // 获取下一个节点
             let next_node = {
@@ -80,12 +109,225 @@ impl VarNode {
         }
     }
 }
+const VAR_MAGIC: [u8; 4] = *b"VARS";
+// 每个节点保存5个u32：到达节点的字符、第一个子节点、子节点个数、是否是单词、值的位置
+const NODE_WORDS: usize = 5;
+const NO_VALUE: u32 = u32::MAX;
+impl VarNode {
+    ///保存为二进制格式，节点按层次顺序排列，同一个节点的子节点按字符排序并且相邻，
+    ///值只能是数据，函数等运行时的值返回`Unsupported`
+    pub fn to_bytes(node: &NodeRef) -> Result<Vec<u8>, SerialError> {
+        let mut order = vec![('\0', node.clone())];
+        let mut nodes = Vec::new();
+        let mut values = Vec::new();
+        let mut index = 0;
+        while index < order.len() {
+            let (c, current) = order[index].clone();
+            let current = current.borrow();
+            let mut child: Vec<_> = current.child.iter().map(|(c, node)| (*c, node.clone())).collect();
+            child.sort_by_key(|(c, _)| *c);
+            let value = match &current.value {
+                Some(value) => {
+                    let offset = values.len() as u32;
+                    write_expr(&mut values, value)?;
+                    offset
+                }
+                None => NO_VALUE,
+            };
+            for field in [c as u32, order.len() as u32, child.len() as u32, current.is_word as u32, value] {
+                put_u32(&mut nodes, field);
+            }
+            order.extend(child);
+            index += 1;
+        }
+        let mut bytes = Vec::with_capacity(8 + nodes.len() + values.len());
+        put_u32(&mut bytes, order.len() as u32);
+        put_u32(&mut bytes, values.len() as u32);
+        bytes.extend(nodes);
+        bytes.extend(values);
+        Ok(serial::seal(VAR_MAGIC, bytes))
+    }
+    ///读取`to_bytes`保存的变量表，重新创建所有节点
+    pub fn from_bytes(bytes: &[u8]) -> Result<NodeRef, SerialError> {
+        let view = VarView::new(bytes)?;
+        let nodes: Vec<NodeRef> = (0..view.len()).map(|_| VarNode::new()).collect();
+        for (id, node) in nodes.iter().enumerate() {
+            let mut node = node.borrow_mut();
+            node.is_word = view.field(id, 3) != 0;
+            node.value = view.value(id);
+            for child in view.children(id) {
+                node.child.insert(view.char(child), nodes[child].clone());
+            }
+        }
+        Ok(nodes[0].clone())
+    }
+}
+///直接在`VarNode::to_bytes`得到的字节上查找变量，值在读取时才解析
+#[derive(Debug, Clone)]
+pub struct VarView<'a> {
+    nodes: &'a [u8],
+    values: &'a [u8],
+}
+impl<'a> VarView<'a> {
+    ///检查头部、校验和、节点的下标和所有的值，之后的查找不会失败
+    pub fn new(bytes: &'a [u8]) -> Result<Self, SerialError> {
+        let mut reader = Reader::new(serial::open(VAR_MAGIC, bytes)?);
+        let count = reader.u32()? as usize;
+        let values_len = reader.u32()? as usize;
+        let nodes = reader.slice(count.checked_mul(NODE_WORDS * 4).ok_or(SerialError::Truncated)?)?;
+        let values = reader.slice(values_len)?;
+        let view = VarView { nodes, values };
+        view.validate()?;
+        Ok(view)
+    }
+    // 子节点在当前节点之后，子节点的字符有效并且递增，值的位置都能解析出完整的值
+    fn validate(&self) -> Result<(), SerialError> {
+        let corrupted = Err(SerialError::Corrupted("invalid variable trie"));
+        if self.len() == 0 {
+            return corrupted;
+        }
+        for id in 0..self.len() {
+            let (first, count) = (self.field(id, 1) as usize, self.field(id, 2) as usize);
+            if count != 0 && (first <= id || first.checked_add(count).is_none_or(|end| end > self.len())) {
+                return corrupted;
+            }
+            let mut last = None;
+            for child in self.children(id) {
+                let c = self.field(child, 0);
+                if char::from_u32(c).is_none() || last.is_some_and(|last| last >= c) {
+                    return corrupted;
+                }
+                last = Some(c);
+            }
+            let offset = self.field(id, 4);
+            if offset != NO_VALUE {
+                let value = self.values.get(offset as usize..).ok_or(SerialError::Truncated)?;
+                read_expr(&mut Reader::new(value))?;
+            }
+        }
+        Ok(())
+    }
+    ///节点的个数，包括根节点
+    pub fn len(&self) -> usize {
+        self.nodes.len() / (NODE_WORDS * 4)
+    }
+    ///获取变量绑定的值
+    pub fn get<S: AsRef<str>>(&self, s: S) -> Option<Expr> {
+        self.value(self.find(s)?)
+    }
+    ///和`VarNode::pattern`相同，判断是否是插入过的单词
+    pub fn pattern<S: AsRef<str>>(&self, s: S) -> bool {
+        self.find(s).is_some_and(|id| self.field(id, 3) != 0)
+    }
+    // 子节点按字符排序，二分查找
+    fn find<S: AsRef<str>>(&self, s: S) -> Option<usize> {
+        let mut current = 0;
+        for c in s.as_ref().chars() {
+            let children = self.children(current);
+            let (mut low, mut high) = (children.start, children.end);
+            current = loop {
+                if low >= high {
+                    return None;
+                }
+                let mid = low + (high - low) / 2;
+                match self.field(mid, 0).cmp(&(c as u32)) {
+                    std::cmp::Ordering::Equal => break mid,
+                    std::cmp::Ordering::Less => low = mid + 1,
+                    std::cmp::Ordering::Greater => high = mid,
+                }
+            };
+        }
+        Some(current)
+    }
+    fn field(&self, id: usize, index: usize) -> u32 {
+        u32_at(self.nodes, id * NODE_WORDS + index)
+    }
+    fn char(&self, id: usize) -> char {
+        char::from_u32(self.field(id, 0)).unwrap_or('\0')
+    }
+    fn children(&self, id: usize) -> std::ops::Range<usize> {
+        let first = self.field(id, 1) as usize;
+        first..first + self.field(id, 2) as usize
+    }
+    // 检查时已经解析过所有的值，这里不会失败
+    fn value(&self, id: usize) -> Option<Expr> {
+        let offset = self.field(id, 4);
+        if offset == NO_VALUE {
+            return None;
+        }
+        read_expr(&mut Reader::new(&self.values[offset as usize..])).ok()
+    }
+}
 pub struct AcManger {
     current_node: NodeRef,
     head: NodeRef,
 }
 impl AcManger {}
 
+pub type EnvRef = Rc<Env>;
+///变量作用域，查找变量时沿着父作用域向上查找
+#[derive(Debug)]
+pub struct Env {
+    vars: NodeRef,
+    parent: Option<EnvRef>,
+    // 全局作用域上的计数器，宏重命名和记录类型的编号在同一个解释器中不重复
+    counter: Cell<usize>,
+}
+
+impl Env {
+    ///创建全局作用域
+    pub fn new() -> EnvRef {
+        Rc::new(Self {
+            vars: VarNode::new(),
+            parent: None,
+            counter: Cell::new(0),
+        })
+    }
+    ///创建子作用域，子作用域中的绑定不会影响父作用域
+    pub fn child(parent: &EnvRef) -> EnvRef {
+        Rc::new(Self {
+            vars: VarNode::new(),
+            parent: Some(parent.clone()),
+            counter: Cell::new(0),
+        })
+    }
+    ///查找变量
+    pub fn get<S: AsRef<str>>(&self, name: S) -> Option<Expr> {
+        let name = name.as_ref();
+        match VarNode::get(&self.vars, name) {
+            Some(v) => Some(v),
+            None => self.parent.as_ref()?.get(name),
+        }
+    }
+    ///在当前作用域中定义变量
+    pub fn def<S: AsRef<str>>(&self, name: S, value: Expr) {
+        VarNode::insert(&self.vars, name, value);
+    }
+    ///全局作用域中的下一个编号
+    pub fn next_id(&self) -> usize {
+        match &self.parent {
+            Some(parent) => parent.next_id(),
+            None => {
+                let id = self.counter.get();
+                self.counter.set(id + 1);
+                id
+            }
+        }
+    }
+    ///修改已经存在的变量，修改的是最近一层作用域中的变量，变量不存在时返回false
+    pub fn set<S: AsRef<str>>(&self, name: S, value: Expr) -> bool {
+        let name = name.as_ref();
+        if VarNode::get(&self.vars, name).is_some() {
+            self.def(name, value);
+            return true;
+        }
+        match &self.parent {
+            Some(parent) => parent.set(name, value),
+            None => false,
+        }
+    }
+}
+
 // 使用示例
 #[test]
 fn test() {
@@ -106,6 +348,69 @@ fn test() {
     
 }
 
+#[test]
+fn insert_test() {
+    let root = VarNode::new();
+    VarNode::insert(&root, "he", Expr::Number(1));
+    VarNode::insert(&root, "her", Expr::Number(2));
+    assert!(matches!(VarNode::get(&root, "he"), Some(Expr::Number(1))));
+    assert!(matches!(VarNode::get(&root, "her"), Some(Expr::Number(2))));
+    assert!(VarNode::get(&root, "h").is_none());
+    VarNode::insert(&root, "he", Expr::Number(3));
+    assert!(matches!(VarNode::get(&root, "he"), Some(Expr::Number(3))));
+}
+
+#[test]
+fn serialize_test() {
+    let root = VarNode::new();
+    VarNode::insert(&root, "he", Expr::Number(1));
+    VarNode::insert(&root, "her", Expr::Str("中文".into()));
+    VarNode::insert(&root, "变量", Expr::QExpr(vec![Expr::Number(2), Expr::Char('x')].into()));
+    VarNode::push_str(&root, "his");
+    let bytes = VarNode::to_bytes(&root).unwrap();
+    let view = VarView::new(&bytes).unwrap();
+    assert!(matches!(view.get("he"), Some(Expr::Number(1))));
+    assert_eq!(VarNode::get(&root, "her"), view.get("her"));
+    assert_eq!(VarNode::get(&root, "变量"), view.get("变量"));
+    assert!(view.get("h").is_none() && view.get("his").is_none());
+    assert!(view.pattern("his") && !view.pattern("hi"));
+    // 多字节字符的变量名也标记为单词
+    VarNode::push_str(&root, "名字");
+    assert!(VarNode::pattern(&root, "名字") && !VarNode::pattern(&root, "名"));
+    let bytes = VarNode::to_bytes(&root).unwrap();
+    let view = VarView::new(&bytes).unwrap();
+    assert!(view.pattern("名字") && view.pattern("变量") && !view.pattern("名"));
+    let loaded = VarNode::from_bytes(&bytes).unwrap();
+    assert_eq!(VarNode::get(&root, "变量"), VarNode::get(&loaded, "变量"));
+    assert!(VarNode::pattern(&loaded, "his"));
+    // 自动机和变量表的魔数不同，不能混用
+    let ac = crate::ac::AcBuilder::new().build(&["he"]).to_bytes();
+    assert_eq!(SerialError::Magic { expected: VAR_MAGIC }, VarView::new(&ac).unwrap_err());
+    let mut newer = bytes.clone();
+    newer[4] = 2;
+    assert_eq!(SerialError::Version { expected: 1, found: 2 }, VarView::new(&newer).unwrap_err());
+    let env = Env::new();
+    VarNode::insert(&root, "f", crate::token::read("\\ {x} {x}").and_then(|expr| expr.eval(&env)).unwrap());
+    assert_eq!(SerialError::Unsupported("function"), VarNode::to_bytes(&root).unwrap_err());
+}
+#[test]
+fn env_test() {
+    let global = Env::new();
+    global.def("x", Expr::Number(1));
+    let local = Env::child(&global);
+    local.def("y", Expr::Number(2));
+    assert!(matches!(local.get("x"), Some(Expr::Number(1))));
+    assert!(matches!(local.get("y"), Some(Expr::Number(2))));
+    assert!(global.get("y").is_none());
+    assert!(local.set("x", Expr::Number(3)));
+    assert!(matches!(global.get("x"), Some(Expr::Number(3))));
+    assert!(!local.set("z", Expr::Number(3)));
+    // 编号由全局作用域分配，不同的解释器互不影响
+    assert_eq!(0, local.next_id());
+    assert_eq!(1, global.next_id());
+    assert_eq!(0, Env::new().next_id());
+}
+
 #[test]
 fn struct_sample_test() {
     let root = VarNode::new();
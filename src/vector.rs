@@ -0,0 +1,92 @@
+use crate::{
+    calc::{arg_type, args_count, extra_num},
+    error::{LispError, LispResult},
+    form::min_args,
+    persistent::TrieVector,
+    token::{Expr, TokensError},
+};
+
+fn extra_vector(expr: &Expr, index: usize) -> LispResult<&TrieVector> {
+    match expr {
+        Expr::Vector(items) => Ok(items),
+        _ => Err(arg_type("vector", expr, index)),
+    }
+}
+fn index(expr: &Expr, arg: usize) -> LispResult<usize> {
+    Ok(extra_num(expr, arg)? as usize)
+}
+fn out_of_bounds(index: usize, items: &TrieVector) -> Box<LispError> {
+    TokensError::IndexOutOfBounds { index, len: items.len() }.into()
+}
+///`vector 1 (+ 1 1)`，参数计算之后组成向量
+pub fn vector(tokens: &[Expr]) -> LispResult<Expr> {
+    Ok(Expr::Vector(tokens.iter().cloned().collect()))
+}
+///`nth v i`，取出下标为i的元素
+pub fn nth(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 2)?;
+    let items = extra_vector(&tokens[0], 0)?;
+    let i = index(&tokens[1], 1)?;
+    items.get(i).cloned().ok_or_else(|| out_of_bounds(i, items))
+}
+///`vset v i x`，返回修改了一个元素的新向量，只复制从根到这个元素的路径
+pub fn vset(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 3)?;
+    let items = extra_vector(&tokens[0], 0)?;
+    let i = index(&tokens[1], 1)?;
+    items.set(i, tokens[2].to_owned()).map(Expr::Vector).ok_or_else(|| out_of_bounds(i, items))
+}
+///`vpush v x ...`，返回在末尾添加了元素的新向量
+pub fn vpush(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 1)?;
+    let items = extra_vector(&tokens[0], 0)?;
+    Ok(Expr::Vector(tokens[1..].iter().fold(items.to_owned(), |v, i| v.push(i.to_owned()))))
+}
+pub fn vlen(tokens: &[Expr]) -> LispResult<Expr> {
+    args_count(tokens, 1)?;
+    Ok(Expr::Number(extra_vector(&tokens[0], 0)?.len() as u32))
+}
+///`subvec v start end`，不包含end，省略end时取到末尾，和原来的向量共享元素
+pub fn subvec(tokens: &[Expr]) -> LispResult<Expr> {
+    min_args(tokens, 2)?;
+    let items = extra_vector(&tokens[0], 0)?;
+    let start = index(&tokens[1], 1)?;
+    let end = match tokens.get(2) {
+        Some(end) => index(end, 2)?,
+        None => items.len(),
+    };
+    if end > items.len() {
+        return Err(out_of_bounds(end, items));
+    }
+    items.slice(start, end).map(Expr::Vector).ok_or_else(|| out_of_bounds(start, items))
+}
+
+#[cfg(test)]
+fn run(s: &str) -> LispResult<Expr> {
+    crate::token::read(s).and_then(|expr| expr.eval(&crate::varmap::Env::new()))
+}
+#[test]
+fn vector_test() {
+    assert_eq!("[ 1 2 3 ]", run("[1 2 3]").unwrap().to_string());
+    assert!(matches!(run("nth [1 2 3] 2").unwrap(), Expr::Number(3)));
+    assert!(matches!(run("vlen (vector 1 (+ 1 1))").unwrap(), Expr::Number(2)));
+    assert_eq!("[ 1 9 3 ]", run("vset [1 2 3] 1 9").unwrap().to_string());
+    assert_eq!("[ 1 2 3 ]", run("vpush [1] 2 3").unwrap().to_string());
+    assert_eq!("[ 2 3 ]", run("subvec [1 2 3 4] 1 3").unwrap().to_string());
+    assert_eq!("{ [ { 1 } [ ] ] }", run("list [{1} []]").unwrap().to_string());
+    assert!(matches!(run("get #{[1 2] 5} [1 2]").unwrap(), Expr::Number(5)));
+}
+#[test]
+fn bounds_test() {
+    let err = run("nth [1 2 3] 3").unwrap_err();
+    assert_eq!("index-error", err.kind);
+    assert_eq!("Index 3 out of bounds for length 3", err.message);
+    assert!(run("vset [] 0 1").is_err());
+    assert!(run("subvec [1 2 3] 2 1").is_err());
+    assert!(run("subvec [1 2 3] 3").is_ok());
+}
+#[test]
+fn type_error_test() {
+    assert_eq!("expected vector, got qexpr at argument 1 of nth", run("nth {1} 0").unwrap_err().message);
+    assert_eq!("expected number, got string at argument 3 of subvec", run("subvec [1] 0 \"x\"").unwrap_err().message);
+}